@@ -0,0 +1,22 @@
+use crate::Document;
+use crate::Position;
+
+// A document that isn't on screen, along with where its cursor, scroll
+// offset and mark were left. The active document lives in the editor
+// itself and is swapped with its `Buffer` when switching.
+#[derive(Default)]
+pub struct Buffer {
+    pub document: Document,
+    pub cursor_position: Position,
+    pub offset: Position,
+    pub mark: Option<Position>,
+}
+
+impl Buffer {
+    pub fn from(document: Document) -> Self {
+        Self {
+            document,
+            ..Self::default()
+        }
+    }
+}
@@ -0,0 +1,334 @@
+use crate::config::ClipboardConfig;
+use crate::Terminal;
+use std::io::{Error, ErrorKind, Write};
+use std::process::{Command, Stdio};
+
+// Somewhere outside of hecto that copied text can be sent to and, for some
+// providers, read back from.
+pub trait Provider {
+    fn copy(&mut self, text: &str) -> Result<(), Error>;
+    // `None` means the provider can't be read from and the editor should use
+    // its own copy of the text.
+    fn paste(&mut self) -> Result<Option<String>, Error>;
+}
+
+// Keeps everything inside the editor.
+pub struct Internal;
+
+impl Provider for Internal {
+    fn copy(&mut self, _text: &str) -> Result<(), Error> {
+        Ok(())
+    }
+    fn paste(&mut self) -> Result<Option<String>, Error> {
+        Ok(None)
+    }
+}
+
+// Hands the text to the terminal with the OSC 52 escape sequence, which works
+// over SSH too. Terminals don't let us read the clipboard back this way.
+pub struct Osc52;
+
+impl Provider for Osc52 {
+    fn copy(&mut self, text: &str) -> Result<(), Error> {
+        Terminal::set_clipboard(text);
+        Terminal::flush()
+    }
+    fn paste(&mut self) -> Result<Option<String>, Error> {
+        Ok(None)
+    }
+}
+
+// Pipes the text into an external helper such as `xclip` or `wl-copy`, and
+// reads it back through an optional second helper such as `wl-paste`.
+pub struct External {
+    copy_command: Vec<String>,
+    paste_command: Option<Vec<String>>,
+}
+
+impl External {
+    pub fn new(copy_command: &str, paste_command: Option<&str>) -> Self {
+        Self {
+            copy_command: split_command(copy_command),
+            paste_command: paste_command.map(split_command),
+        }
+    }
+}
+
+impl Provider for External {
+    fn copy(&mut self, text: &str) -> Result<(), Error> {
+        let (program, args) = self
+            .copy_command
+            .split_first()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "no copy command"))?;
+        // The helper must not write to our screen.
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        let status = child.wait()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(Error::other(format!("{program} exited with {status}")))
+        }
+    }
+
+    fn paste(&mut self) -> Result<Option<String>, Error> {
+        let Some((program, args)) = self.paste_command.as_ref().and_then(|command| command.split_first()) else {
+            return Ok(None);
+        };
+        let output = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()?;
+        if output.status.success() {
+            Ok(Some(String::from_utf8_lossy(&output.stdout).into_owned()))
+        } else {
+            Err(Error::other(format!("{program} exited with {}", output.status)))
+        }
+    }
+}
+
+fn split_command(command: &str) -> Vec<String> {
+    command.split_whitespace().map(str::to_string).collect()
+}
+
+// How many kills the kill ring remembers.
+const KILL_RING_SIZE: usize = 60;
+
+// The editor's clipboard: keeps its own ring of recent kills, newest last,
+// and mirrors the newest one to a provider.
+pub struct Clipboard {
+    ring: Vec<String>,
+    // The entry the last paste or rotation produced.
+    yank_index: usize,
+    provider: Box<dyn Provider>,
+}
+
+impl Clipboard {
+    pub fn new(provider: Box<dyn Provider>) -> Self {
+        Self {
+            ring: Vec::new(),
+            yank_index: 0,
+            provider,
+        }
+    }
+
+    // Picks the provider named in the `[clipboard]` section of the config,
+    // falling back to OSC 52.
+    pub fn from_config(config: &ClipboardConfig) -> Self {
+        let provider: Box<dyn Provider> = match (config.provider.as_str(), &config.copy_command) {
+            ("internal", _) => Box::new(Internal),
+            ("command", Some(copy_command)) => Box::new(External::new(
+                copy_command,
+                config.paste_command.as_deref(),
+            )),
+            _ => Box::new(Osc52),
+        };
+        Self::new(provider)
+    }
+
+    // The text is kept even if the provider fails, so pasting inside hecto
+    // still works.
+    pub fn copy(&mut self, text: String) -> Result<(), Error> {
+        let result = self.provider.copy(&text);
+        self.push(text);
+        result
+    }
+
+    // Adds to the newest entry, so that several kills in a row yank back
+    // as one.
+    pub fn append(&mut self, text: &str) -> Result<(), Error> {
+        let Some(last) = self.ring.last_mut() else {
+            return self.copy(text.to_string());
+        };
+        last.push_str(text);
+        let last = last.clone();
+        self.yank_index = self.ring.len().saturating_sub(1);
+        self.provider.copy(&last)
+    }
+
+    pub fn paste(&mut self) -> Result<String, Error> {
+        // Text copied outside of hecto joins the ring like any other kill.
+        if let Some(text) = self.provider.paste()? {
+            if self.ring.last() != Some(&text) {
+                self.push(text);
+            }
+        }
+        self.yank_index = self.ring.len().saturating_sub(1);
+        Ok(self.ring.last().cloned().unwrap_or_default())
+    }
+
+    // Steps back to the kill before the one yanked last, wrapping around.
+    pub fn rotate(&mut self) -> Option<String> {
+        let len = self.ring.len();
+        if len == 0 {
+            return None;
+        }
+        self.yank_index = self.yank_index.checked_sub(1).unwrap_or(len.saturating_sub(1));
+        self.ring.get(self.yank_index).cloned()
+    }
+
+    fn push(&mut self, text: String) {
+        if self.ring.len() >= KILL_RING_SIZE {
+            self.ring.remove(0);
+        }
+        self.ring.push(text);
+        self.yank_index = self.ring.len().saturating_sub(1);
+    }
+}
+
+// Standard base64, as required by OSC 52.
+#[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
+pub fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut result = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b0 = u32::from(chunk[0]);
+        let b1 = chunk.get(1).map_or(0, |b| u32::from(*b));
+        let b2 = chunk.get(2).map_or(0, |b| u32::from(*b));
+        let triple = (b0 << 16) | (b1 << 8) | b2;
+        result.push(ALPHABET[(triple >> 18) as usize & 63] as char);
+        result.push(ALPHABET[(triple >> 12) as usize & 63] as char);
+        if chunk.len() > 1 {
+            result.push(ALPHABET[(triple >> 6) as usize & 63] as char);
+        } else {
+            result.push('=');
+        }
+        if chunk.len() > 2 {
+            result.push(ALPHABET[triple as usize & 63] as char);
+        } else {
+            result.push('=');
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // Stands in for the system clipboard, shared with the test so it can
+    // look at what was copied and put text there itself.
+    #[derive(Clone, Default)]
+    struct Fake {
+        contents: Rc<RefCell<Option<String>>>,
+        fail: bool,
+    }
+
+    impl Provider for Fake {
+        fn copy(&mut self, text: &str) -> Result<(), Error> {
+            if self.fail {
+                return Err(Error::other("unavailable"));
+            }
+            *self.contents.borrow_mut() = Some(text.to_string());
+            Ok(())
+        }
+        fn paste(&mut self) -> Result<Option<String>, Error> {
+            Ok(self.contents.borrow().clone())
+        }
+    }
+
+    #[test]
+    fn copy_reaches_the_provider() {
+        let fake = Fake::default();
+        let mut clipboard = Clipboard::new(Box::new(fake.clone()));
+        clipboard.copy("hello".to_string()).unwrap();
+        assert_eq!(fake.contents.borrow().as_deref(), Some("hello"));
+        assert_eq!(clipboard.paste().unwrap(), "hello");
+    }
+
+    #[test]
+    fn paste_reads_text_copied_elsewhere() {
+        let fake = Fake::default();
+        let mut clipboard = Clipboard::new(Box::new(fake.clone()));
+        clipboard.copy("ours".to_string()).unwrap();
+        *fake.contents.borrow_mut() = Some("theirs".to_string());
+        assert_eq!(clipboard.paste().unwrap(), "theirs");
+        // It joined the ring, so the earlier kill is one rotation away.
+        assert_eq!(clipboard.rotate().as_deref(), Some("ours"));
+    }
+
+    #[test]
+    fn failed_copy_still_pastes_inside_the_editor() {
+        let fake = Fake {
+            fail: true,
+            ..Fake::default()
+        };
+        let mut clipboard = Clipboard::new(Box::new(fake));
+        assert!(clipboard.copy("kept".to_string()).is_err());
+        assert_eq!(clipboard.paste().unwrap(), "kept");
+    }
+
+    #[test]
+    fn internal_provider_keeps_the_ring() {
+        let mut clipboard = Clipboard::new(Box::new(Internal));
+        assert_eq!(clipboard.paste().unwrap(), "");
+        clipboard.copy("one".to_string()).unwrap();
+        clipboard.append(" two").unwrap();
+        clipboard.copy("three".to_string()).unwrap();
+        assert_eq!(clipboard.paste().unwrap(), "three");
+        assert_eq!(clipboard.rotate().as_deref(), Some("one two"));
+        assert_eq!(clipboard.rotate().as_deref(), Some("three"));
+    }
+
+    #[test]
+    fn appending_updates_the_provider() {
+        let fake = Fake::default();
+        let mut clipboard = Clipboard::new(Box::new(fake.clone()));
+        // With nothing to add to, the first kill starts an entry.
+        clipboard.append("one").unwrap();
+        clipboard.append("\ntwo").unwrap();
+        assert_eq!(fake.contents.borrow().as_deref(), Some("one\ntwo"));
+        assert_eq!(clipboard.paste().unwrap(), "one\ntwo");
+        assert_eq!(clipboard.rotate().as_deref(), Some("one\ntwo"));
+    }
+
+    #[test]
+    fn ring_forgets_the_oldest_kills() {
+        let mut clipboard = Clipboard::new(Box::new(Internal));
+        assert!(clipboard.rotate().is_none());
+        for kill in 0..=KILL_RING_SIZE {
+            clipboard.copy(kill.to_string()).unwrap();
+        }
+        assert_eq!(clipboard.ring.len(), KILL_RING_SIZE);
+        assert_eq!(clipboard.paste().unwrap(), KILL_RING_SIZE.to_string());
+        let mut rotated = Vec::new();
+        for _ in 0..KILL_RING_SIZE {
+            rotated.push(clipboard.rotate().unwrap());
+        }
+        // Kill 0 is gone, and the last rotation wraps around to the newest.
+        assert_eq!(rotated.first().map(String::as_str), Some("59"));
+        assert_eq!(rotated.get(58).map(String::as_str), Some("1"));
+        assert_eq!(rotated.last(), Some(&KILL_RING_SIZE.to_string()));
+    }
+
+    #[test]
+    fn config_picks_the_command_provider() {
+        let config = ClipboardConfig {
+            provider: "command".to_string(),
+            copy_command: Some("true".to_string()),
+            paste_command: Some("echo pasted".to_string()),
+        };
+        let mut clipboard = Clipboard::from_config(&config);
+        clipboard.copy("copied".to_string()).unwrap();
+        assert_eq!(clipboard.paste().unwrap(), "pasted\n");
+    }
+
+    #[test]
+    fn base64_pads_partial_chunks() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64("é".as_bytes()), "w6k=");
+    }
+}
@@ -0,0 +1,306 @@
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Every key that may appear in the config file, by section, so that typos
+// can be reported instead of silently ignored.
+const TOP_LEVEL_KEYS: &[&str] = &[
+    "quit_times",
+    "message_timeout",
+    "tab_width",
+    "theme",
+    "persistent_undo",
+    "clipboard",
+    "keys",
+    "preset",
+    "line_numbers",
+    "wrap",
+    "swap_interval",
+    "autosave_idle",
+    "autosave_on_focus_loss",
+];
+const CLIPBOARD_KEYS: &[&str] = &["provider", "copy_command", "paste_command"];
+
+// Editor behavior, read from `config.toml` at startup. Anything missing from
+// the file keeps its default.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // How many extra times Ctrl-Q has to be pressed to quit with unsaved changes.
+    pub quit_times: u8,
+    // Seconds a message stays in the message bar.
+    pub message_timeout: u64,
+    // Columns between tab stops.
+    pub tab_width: usize,
+    // Name of a theme in `<config dir>/themes`.
+    pub theme: Option<String>,
+    // Whether the undo history is kept in a file next to the document.
+    pub persistent_undo: bool,
+    pub clipboard: ClipboardConfig,
+    // Key sequences such as "ctrl-x ctrl-s" mapped to command names, on top
+    // of the default bindings.
+    pub keys: BTreeMap<String, String>,
+    // `standard`, `emacs`, or `vim` for modal editing.
+    pub preset: String,
+    pub line_numbers: LineNumbers,
+    pub wrap: Wrap,
+    // Seconds between writes of unsaved changes to swap files, or 0 to not
+    // keep swap files at all.
+    pub swap_interval: u64,
+    // Seconds without input after which modified files are saved, or 0 to
+    // only save when asked to.
+    pub autosave_idle: u64,
+    // Whether modified files are saved when the terminal loses focus.
+    pub autosave_on_focus_loss: bool,
+}
+
+// What the gutter left of the text shows.
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LineNumbers {
+    // No gutter at all.
+    Off,
+    Absolute,
+    // Distance from the cursor line, which shows 0.
+    Relative,
+    // Like relative, but the cursor line shows its own number.
+    Hybrid,
+}
+
+// How rows wider than the screen are shown.
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Wrap {
+    // Cut off, with the view scrolling sideways to follow the cursor.
+    Off,
+    // Continued on the next screen line at any character.
+    Anywhere,
+    // Continued on the next screen line after a space where possible.
+    Words,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ClipboardConfig {
+    // `osc52`, `command` or `internal`.
+    pub provider: String,
+    // Used by the `command` provider, e.g. `xclip -selection clipboard`.
+    pub copy_command: Option<String>,
+    pub paste_command: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            quit_times: 2,
+            message_timeout: 5,
+            tab_width: 4,
+            theme: None,
+            persistent_undo: true,
+            clipboard: ClipboardConfig::default(),
+            keys: BTreeMap::new(),
+            preset: String::from("standard"),
+            line_numbers: LineNumbers::Off,
+            wrap: Wrap::Off,
+            swap_interval: 4,
+            autosave_idle: 0,
+            autosave_on_focus_loss: false,
+        }
+    }
+}
+
+impl Default for ClipboardConfig {
+    fn default() -> Self {
+        Self {
+            provider: String::from("osc52"),
+            copy_command: None,
+            paste_command: None,
+        }
+    }
+}
+
+impl Config {
+    // Reads the config from `path`, or from `$HECTO_CONFIG`, or from
+    // `<config dir>/config.toml`. Problems are returned as messages for the
+    // message bar; whatever could be read is still used.
+    pub fn load(path: Option<&str>) -> (Self, Vec<String>) {
+        let explicit = path
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HECTO_CONFIG").map(PathBuf::from));
+        let (path, required) = match explicit {
+            Some(path) => (path, true),
+            None => match config_dir() {
+                Some(dir) => (dir.join("config.toml"), false),
+                None => return (Self::default(), Vec::new()),
+            },
+        };
+        match fs::read_to_string(&path) {
+            Ok(contents) => Self::parse(&contents),
+            // Not having a config file at the default location is normal.
+            Err(_) if !required => (Self::default(), Vec::new()),
+            Err(error) => (
+                Self::default(),
+                vec![format!("config {}: {error}", path.display())],
+            ),
+        }
+    }
+
+    // A value that can't be read is reported and left at its default; the
+    // rest of the file still applies.
+    pub fn parse(contents: &str) -> (Self, Vec<String>) {
+        let mut table = match contents.parse::<toml::Table>() {
+            Ok(table) => table,
+            Err(error) => {
+                let error = format!("config: {}", describe_toml_error(&error, contents));
+                return (Self::default(), vec![error]);
+            }
+        };
+        // Unknown keys are found on the raw table, since serde would
+        // silently skip them.
+        let mut errors = Vec::new();
+        report_unknown_keys(&table, TOP_LEVEL_KEYS, "", &mut errors);
+        if let Some(toml::Value::Table(clipboard)) = table.remove("clipboard") {
+            report_unknown_keys(&clipboard, CLIPBOARD_KEYS, "clipboard.", &mut errors);
+            let clipboard = readable_keys::<ClipboardConfig>(clipboard, "clipboard.", &mut errors);
+            table.insert(String::from("clipboard"), toml::Value::Table(clipboard));
+        }
+        let table = readable_keys::<Self>(table, "", &mut errors);
+        let mut config = toml::Value::Table(table).try_into::<Self>().unwrap_or_default();
+        errors.extend(config.validate());
+        (config, errors)
+    }
+
+    // Resets values that parse fine but can't be used to their defaults.
+    fn validate(&mut self) -> Vec<String> {
+        let defaults = Self::default();
+        let mut errors = Vec::new();
+        if self.tab_width == 0 {
+            errors.push(String::from("config: `tab_width` must be at least 1"));
+            self.tab_width = defaults.tab_width;
+        }
+        if !["standard", "emacs", "vim"].contains(&self.preset.as_str()) {
+            errors.push(format!("config: unknown preset `{}`", self.preset));
+            self.preset = defaults.preset;
+        }
+        let clipboard = &self.clipboard;
+        let error = match clipboard.provider.as_str() {
+            "osc52" | "internal" => None,
+            "command" if clipboard.copy_command.is_none() => Some(String::from(
+                "config: clipboard provider `command` needs `clipboard.copy_command`",
+            )),
+            "command" => None,
+            other => Some(format!("config: unknown clipboard provider `{other}`")),
+        };
+        if let Some(error) = error {
+            errors.push(error);
+            self.clipboard = defaults.clipboard;
+        }
+        errors
+    }
+}
+
+// The keys of `table` whose values `T` can read, with an error for each of
+// the others.
+fn readable_keys<T: DeserializeOwned>(table: toml::Table, prefix: &str, errors: &mut Vec<String>) -> toml::Table {
+    table
+        .into_iter()
+        .filter(|(key, value)| {
+            let single = toml::Table::from_iter([(key.clone(), value.clone())]);
+            match toml::Value::Table(single).try_into::<T>() {
+                Ok(_) => true,
+                Err(error) => {
+                    errors.push(format!("config: `{prefix}{key}`: {}", describe_toml_error(&error, "")));
+                    false
+                }
+            }
+        })
+        .collect()
+}
+
+fn report_unknown_keys(table: &toml::Table, known: &[&str], prefix: &str, errors: &mut Vec<String>) {
+    for key in table.keys() {
+        if !known.contains(&key.as_str()) {
+            errors.push(format!("config: unknown key `{prefix}{key}`"));
+        }
+    }
+}
+
+// A TOML parse error as one line for the message bar, with the line of
+// `contents` it was found on if known.
+pub fn describe_toml_error(error: &toml::de::Error, contents: &str) -> String {
+    // The full error spans several lines; the message bar has one.
+    let message = error.message().lines().next().unwrap_or_default();
+    match error.span() {
+        Some(span) => {
+            let line = contents
+                .get(..span.start)
+                .map_or(0, |before| before.matches('\n').count())
+                .saturating_add(1);
+            format!("line {line}: {message}")
+        }
+        None => message.to_string(),
+    }
+}
+
+// `$XDG_CONFIG_HOME/hecto`, or `~/.config/hecto`.
+pub fn config_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .map(|dir| dir.join("hecto"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn syntax_errors_name_the_line() {
+        let (config, errors) = Config::parse("tab_width = 2\nwrap = = 3\nquit_times = 1\n");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("config: line 2: "), "{errors:?}");
+        assert_eq!(config.tab_width, Config::default().tab_width);
+    }
+
+    #[test]
+    fn bad_values_leave_the_rest_of_the_file_alone() {
+        let (config, errors) = Config::parse(
+            "tab_width = 2\nwrap = 3\nquit_times = \"many\"\n[clipboard]\nprovider = \"internal\"\ncopy_command = 1\n",
+        );
+        assert_eq!(errors.len(), 3, "{errors:?}");
+        assert!(errors[0].starts_with("config: `clipboard.copy_command`: "), "{errors:?}");
+        assert!(errors[1].starts_with("config: `quit_times`: "), "{errors:?}");
+        assert!(errors[2].starts_with("config: `wrap`: "), "{errors:?}");
+        assert_eq!(config.tab_width, 2);
+        assert_eq!(config.quit_times, Config::default().quit_times);
+        assert!(config.wrap == Wrap::Off);
+        assert_eq!(config.clipboard.provider, "internal");
+    }
+
+    #[test]
+    fn unknown_keys_and_bad_values_are_reported() {
+        let (config, errors) = Config::parse("tab_width = 0\ncolour = 1\n[clipboard]\nprovider = \"command\"\n");
+        assert_eq!(
+            errors,
+            vec![
+                "config: unknown key `colour`",
+                "config: `tab_width` must be at least 1",
+                "config: clipboard provider `command` needs `clipboard.copy_command`",
+            ]
+        );
+        assert_eq!(config.tab_width, 4);
+        assert_eq!(config.clipboard.provider, "osc52");
+    }
+
+    #[test]
+    fn missing_keys_keep_their_defaults() {
+        let (config, errors) = Config::parse("quit_times = 5\n");
+        assert!(errors.is_empty());
+        assert_eq!(config.quit_times, 5);
+        assert_eq!(config.swap_interval, Config::default().swap_interval);
+    }
+}
@@ -1,28 +1,149 @@
+use crate::encoding::{self, Encoding};
+use crate::history::{self, Edit, History};
+use crate::Config;
+use crate::FileType;
+use crate::FileTypes;
 use crate::Position;
 use crate::Row;
+use crate::SearchDirection;
+use crate::swap;
+use regex::Regex;
+use std::cmp;
 use std::fs;
-use std::io::{Error, Write};
+use std::io::{Error, ErrorKind, Write};
+use std::os::unix::fs::{chown, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process;
+use unicode_segmentation::UnicodeSegmentation;
+
+// Suffixes of the files kept next to the file being edited.
+const HISTORY_SUFFIX: &str = "hecto-undo";
+const SWAP_SUFFIX: &str = "hecto-swp";
 
 #[derive(Default)]
 pub struct Document {
     rows: Vec<Row>,
     pub file_name: Option<String>,
     dirty: bool,
+    history: History,
+    file_type: FileType,
+    highlighted_until: usize,
+    // Whether the undo history is read from and written to its sidecar file.
+    persistent_undo: bool,
+    // A hash of what was last written to the swap file, if this session
+    // wrote one.
+    swap_hash: Option<u64>,
+    // How the file on disk separates lines, and how it will be saved.
+    line_ending: LineEnding,
+    saved_line_ending: LineEnding,
+    // Whether the last line ends with a line ending too.
+    trailing_newline: bool,
+    encoding: Encoding,
+    // Whether the file starts with a byte order mark.
+    bom: bool,
+    // Whether the file had bytes that couldn't be decoded. Until the user
+    // accepts losing them, saving is refused.
+    lossy: bool,
+}
+
+#[derive(Default, Clone, Copy, PartialEq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Lf => "LF",
+            Self::CrLf => "CRLF",
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::CrLf => "\r\n",
+        }
+    }
 }
 
 impl Document {
-    pub fn open(filename: &str) -> Result<Self, Error> {
-        let contents = fs::read_to_string(filename)?;
+    // An empty, unnamed document.
+    pub fn new(config: &Config) -> Self {
+        Self {
+            persistent_undo: config.persistent_undo,
+            trailing_newline: true,
+            ..Self::default()
+        }
+    }
+
+    // An unnamed document holding `text`, for showing rather than editing.
+    pub fn from_text(text: &str, config: &Config) -> Self {
+        Self {
+            rows: text.lines().map(Row::from).collect(),
+            ..Self::new(config)
+        }
+    }
+
+    pub fn open(filename: &str, file_types: &FileTypes, config: &Config) -> Result<Self, Error> {
+        let bytes = fs::read(filename)?;
+        let encoding::Decoded {
+            text,
+            encoding,
+            bom,
+            lossy,
+        } = encoding::decode(&bytes);
         let mut rows = Vec::new();
-        for value in contents.lines() {
+        for value in text.lines() {
             rows.push(Row::from(value));
         }
+        // Files that mix both are saved with whichever ending most lines have.
+        let crlf = text.matches("\r\n").count();
+        let line_ending = if crlf > text.matches('\n').count().saturating_sub(crlf) {
+            LineEnding::CrLf
+        } else {
+            LineEnding::Lf
+        };
+        // A stale or unreadable history file just means starting afresh.
+        let hash = history::content_hash(&bytes);
+        let history = Some(sidecar_path(filename, HISTORY_SUFFIX))
+            .filter(|_| config.persistent_undo)
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|saved| History::deserialize(&saved, hash))
+            .unwrap_or_default();
         Ok(Self {
             rows,
             file_name: Some(filename.to_string()),
             dirty: false,
+            history,
+            file_type: file_types.detect(filename),
+            highlighted_until: 0,
+            persistent_undo: config.persistent_undo,
+            swap_hash: None,
+            line_ending,
+            saved_line_ending: line_ending,
+            // An empty file gets a newline once there is a line to end.
+            trailing_newline: text.is_empty() || text.ends_with('\n'),
+            encoding,
+            bom,
+            lossy,
         })
     }
+    pub fn file_type(&self) -> String {
+        self.file_type.name()
+    }
+
+    // Switches languages, e.g. after "Save as" picked a new extension.
+    pub fn set_file_type(&mut self, file_type: FileType) {
+        self.file_type = file_type;
+        for row in &mut self.rows {
+            row.unhighlight();
+        }
+        self.highlighted_until = 0;
+    }
+
     pub fn row(&self, index: usize) -> Option<&Row> {
         self.rows.get(index)
     }
@@ -37,32 +158,60 @@ impl Document {
         if at.y > self.len() {
             return;
         }
-        if at.y == self.len() {
-            self.rows.push(Row::default());
-            return;
-        }
-        let new_row = self.rows.get_mut(at.y).unwrap().split(at.x);
-
-        #[allow(clippy::integer_arithmetic)]
-        self.rows.insert(at.y + 1, new_row);
+        self.split_row(at);
+        self.record(
+            Edit::Split { at: *at },
+            *at,
+            Position {
+                x: 0,
+                y: at.y.saturating_add(1),
+            },
+        );
     }
 
     pub fn insert(&mut self, at: &Position, c: char) {
         if at.y > self.len(){
             return;
         }
-        self.dirty = true;
         if c == '\n' {
             self.insert_newline(at);
             return;
         }
+        let after = Position {
+            x: at.x.saturating_add(1),
+            y: at.y,
+        };
         if at.y == self.len() {
+            // Typing past the last row starts a new one; undoing the
+            // character should remove that row again.
             let mut row = Row::default();
             row.insert(0, c);
             self.rows.push(row);
+            let at = Position { x: 0, y: at.y };
+            self.record(Edit::Split { at }, at, at);
+            self.record(
+                Edit::Insert {
+                    at,
+                    text: c.to_string(),
+                },
+                at,
+                after,
+            );
         } else {
             let row = self.rows.get_mut(at.y).unwrap();
+            let at = Position {
+                x: cmp::min(at.x, row.len()),
+                y: at.y,
+            };
             row.insert(at.x, c);
+            self.record(
+                Edit::Insert {
+                    at,
+                    text: c.to_string(),
+                },
+                at,
+                after,
+            );
         }
     }
 
@@ -73,27 +222,975 @@ impl Document {
             return;
         }
         if at.x == self.rows.get_mut(at.y).unwrap().len() && at.y + 1 < len{
-            let next_row = self.rows.remove(at.y + 1);
-            let row = self.rows.get_mut(at.y).unwrap();
-            row.append(&next_row);
+            self.join_row(at.y);
+            self.record(Edit::Join { at: *at }, *at, *at);
         } else {
             let row = self.rows.get_mut(at.y).unwrap();
-            row.delete(at.x);
+            let text = row.delete_range(at.x, 1);
+            if !text.is_empty() {
+                self.record(Edit::Delete { at: *at, text }, *at, *at);
+            }
+        }
+    }
+
+    // Inserts `text`, which may span several lines, as a single edit and
+    // returns the position right after it.
+    #[allow(clippy::integer_arithmetic)]
+    pub fn insert_str(&mut self, at: &Position, text: &str) -> Position {
+        if at.y > self.len() || text.is_empty() {
+            return *at;
+        }
+        self.history.begin_group();
+        if at.y == self.len() {
+            self.rows.push(Row::default());
+            self.record(Edit::Split { at: *at }, *at, *at);
+        }
+        let row = self.rows.get_mut(at.y).unwrap();
+        let at = Position {
+            x: cmp::min(at.x, row.len()),
+            y: at.y,
+        };
+        let lines: Vec<&str> = text.split('\n').collect();
+        let mut edits = Vec::new();
+        let mut position = at;
+        if let [line] = lines[..] {
+            row.insert_str(at.x, line);
+            edits.push(Edit::Insert {
+                at,
+                text: line.to_string(),
+            });
+            position.x += line[..].graphemes(true).count();
+        } else {
+            // Build all the new rows first and splice them in at once, so
+            // large pastes don't shift the rows below once per line.
+            let tail = row.split(at.x);
+            let mut new_rows = Vec::with_capacity(lines.len());
+            for (index, line) in lines.iter().enumerate() {
+                let line_len = line[..].graphemes(true).count();
+                if index == 0 {
+                    row.insert_str(at.x, line);
+                } else {
+                    new_rows.push(Row::from(*line));
+                }
+                if !line.is_empty() {
+                    edits.push(Edit::Insert {
+                        at: position,
+                        text: (*line).to_string(),
+                    });
+                }
+                position.x += line_len;
+                if index + 1 < lines.len() {
+                    edits.push(Edit::Split { at: position });
+                    position = Position {
+                        x: 0,
+                        y: position.y + 1,
+                    };
+                }
+            }
+            if let Some(last) = new_rows.last_mut() {
+                last.append(&tail);
+            }
+            self.rows.splice(at.y + 1..at.y + 1, new_rows);
+        }
+        for edit in edits {
+            self.record(edit, at, position);
+        }
+        self.history.end_group();
+        position
+    }
+
+    // Removes the text between `start` and `end` (exclusive), which may span
+    // several lines, as a single edit and returns it.
+    #[allow(clippy::integer_arithmetic)]
+    pub fn delete_range(&mut self, start: &Position, end: &Position) -> String {
+        if start.y >= self.len() || (end.y, end.x) <= (start.y, start.x) {
+            return String::new();
+        }
+        let end = if end.y >= self.len() {
+            let y = self.len() - 1;
+            Position {
+                x: self.rows[y].len(),
+                y,
+            }
+        } else {
+            *end
+        };
+        let mut edits = Vec::new();
+        let row = self.rows.get_mut(start.y).unwrap();
+        let removed = if start.y == end.y {
+            let text = row.delete_range(start.x, end.x.saturating_sub(start.x));
+            edits.push(Edit::Delete {
+                at: *start,
+                text: text.clone(),
+            });
+            text
+        } else {
+            let first = row.delete_range(start.x, row.len().saturating_sub(start.x));
+            let mut removed = vec![first.clone()];
+            edits.push(Edit::Delete {
+                at: *start,
+                text: first,
+            });
+            let mut drained: Vec<Row> = self.rows.drain(start.y + 1..=end.y).collect();
+            let mut last = drained.pop().unwrap_or_default();
+            // Recorded as join-then-delete per row so undo can rebuild them.
+            for row in drained {
+                let text = row.substring(0, row.len());
+                edits.push(Edit::Join { at: *start });
+                edits.push(Edit::Delete {
+                    at: *start,
+                    text: text.clone(),
+                });
+                removed.push(text);
+            }
+            let text = last.delete_range(0, end.x);
+            edits.push(Edit::Join { at: *start });
+            edits.push(Edit::Delete {
+                at: *start,
+                text: text.clone(),
+            });
+            removed.push(text);
+            self.rows.get_mut(start.y).unwrap().append(&last);
+            removed.join("\n")
+        };
+        self.history.begin_group();
+        for edit in edits {
+            self.record(edit, end, *start);
+        }
+        self.history.end_group();
+        removed
+    }
+
+    // The text between `start` and `end` (exclusive), lines joined with `\n`.
+    pub fn text_range(&self, start: &Position, end: &Position) -> String {
+        let mut lines = Vec::new();
+        for y in start.y..=end.y {
+            if let Some(row) = self.rows.get(y) {
+                let from = if y == start.y { start.x } else { 0 };
+                let to = if y == end.y { end.x } else { row.len() };
+                lines.push(row.substring(from, to));
+            }
+        }
+        lines.join("\n")
+    }
+
+    pub fn undo(&mut self) -> Option<Position> {
+        let (edits, cursor) = self.history.undo()?;
+        for edit in edits.iter().rev() {
+            self.apply(edit, true);
+        }
+        self.dirty = !self.history.is_at_saved();
+        Some(cursor)
+    }
+
+    pub fn redo(&mut self) -> Option<Position> {
+        let (edits, cursor) = self.history.redo()?;
+        for edit in &edits {
+            self.apply(edit, false);
+        }
+        self.dirty = !self.history.is_at_saved();
+        Some(cursor)
+    }
+
+    // Walks the undo tree in the order states were created, crossing
+    // branches that plain undo/redo can't reach.
+    pub fn travel(&mut self, forward: bool) -> Option<Position> {
+        let (steps, cursor) = self.history.travel(forward)?;
+        for (edits, undo) in &steps {
+            if *undo {
+                for edit in edits.iter().rev() {
+                    self.apply(edit, true);
+                }
+            } else {
+                for edit in edits {
+                    self.apply(edit, false);
+                }
+            }
+        }
+        self.dirty = !self.history.is_at_saved();
+        Some(cursor)
+    }
+
+    pub fn history_state(&self) -> (usize, usize) {
+        self.history.state()
+    }
+
+    // Edits made between these two calls are undone as a single step.
+    pub fn begin_undo_group(&mut self) {
+        self.history.begin_group();
+    }
+
+    pub fn end_undo_group(&mut self) {
+        self.history.end_group();
+    }
+
+    fn record(&mut self, edit: Edit, before: Position, after: Position) {
+        self.invalidate_highlighting(edit.at().y);
+        self.history.record(edit, before, after);
+        self.dirty = !self.history.is_at_saved();
+    }
+
+    // Applies an edit from the journal, or reverts it when `undo` is set.
+    fn apply(&mut self, edit: &Edit, undo: bool) {
+        self.invalidate_highlighting(edit.at().y);
+        match (edit, undo) {
+            (Edit::Insert { at, text }, false) | (Edit::Delete { at, text }, true) => {
+                if let Some(row) = self.rows.get_mut(at.y) {
+                    row.insert_str(at.x, text);
+                }
+            }
+            (Edit::Insert { at, text }, true) | (Edit::Delete { at, text }, false) => {
+                if let Some(row) = self.rows.get_mut(at.y) {
+                    row.delete_range(at.x, text[..].graphemes(true).count());
+                }
+            }
+            (Edit::Split { at }, false) | (Edit::Join { at }, true) => self.split_row(at),
+            (Edit::Split { at }, true) | (Edit::Join { at }, false) => {
+                if at.y.saturating_add(1) < self.rows.len() {
+                    self.join_row(at.y);
+                } else if at.y < self.rows.len() {
+                    // The split appended a row past the end of the document.
+                    self.rows.remove(at.y);
+                }
+            }
+        }
+    }
+
+    // Splits the row at `at`, or appends an empty row when `at` is past the end.
+    fn split_row(&mut self, at: &Position) {
+        if at.y >= self.len() {
+            self.rows.push(Row::default());
+            return;
+        }
+        let new_row = self.rows.get_mut(at.y).unwrap().split(at.x);
+
+        #[allow(clippy::integer_arithmetic)]
+        self.rows.insert(at.y + 1, new_row);
+    }
+
+    // Appends the row below `y` to it.
+    #[allow(clippy::integer_arithmetic)]
+    fn join_row(&mut self, y: usize) {
+        if y + 1 >= self.len() {
+            return;
         }
+        let next_row = self.rows.remove(y + 1);
+        let row = self.rows.get_mut(y).unwrap();
+        row.append(&next_row);
+    }
+
+    // The text as it is written to disk.
+    fn contents(&self) -> Result<Vec<u8>, Error> {
+        encoding::encode(&self.text(), self.encoding, self.bom)
     }
 
     pub fn save(&mut self) -> Result<(), Error>{
+        if self.lossy {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("the file isn't valid {}; saving would replace the bad bytes", self.encoding.name()),
+            ));
+        }
         if let Some(file_name) = &self.file_name{
-            let mut file = fs::File::create(file_name)?;
-            for row in &self.rows{
-                file.write_all(row.as_bytes())?;
-                file.write_all(b"\n")?;
-            }
+            let contents = self.contents()?;
+            write_atomically(Path::new(file_name), &contents)?;
+            self.history.mark_saved();
             self.dirty = false;
+            self.saved_line_ending = self.line_ending;
+            remove_swap_file(&sidecar_path(file_name, SWAP_SUFFIX));
+            self.swap_hash = None;
+
+            // The file itself is safe at this point; failing to keep its
+            // history around is not worth reporting as a failed save.
+            if self.persistent_undo {
+                let hash = history::content_hash(&contents);
+                let path = sidecar_path(file_name, HISTORY_SUFFIX);
+                let _ = write_private(&path, self.history.serialize(hash).as_bytes());
+            }
         }
         Ok(())
     }
     pub fn is_dirty(&self) -> bool{
-        self.dirty
+        self.dirty || self.line_ending != self.saved_line_ending
+    }
+
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    // Changes how lines are separated when the document is next saved.
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        self.line_ending = line_ending;
+    }
+
+    // Brings the swap file up to date with the unsaved changes, or removes it
+    // once there are none left.
+    pub fn update_swap(&mut self) -> Result<(), Error> {
+        let Some(file_name) = &self.file_name else {
+            return Ok(());
+        };
+        let path = sidecar_path(file_name, SWAP_SUFFIX);
+        if !self.is_dirty() {
+            if self.swap_hash.take().is_some() {
+                remove_swap_file(&path);
+            }
+            return Ok(());
+        }
+        // Swap files are always UTF-8, so that nothing is lost to an
+        // encoding without room for it.
+        let text = self.text();
+        let hash = history::content_hash(text.as_bytes());
+        if self.swap_hash == Some(hash) {
+            return Ok(());
+        }
+        write_private(&path, swap::contents(&text).as_bytes())?;
+        self.swap_hash = Some(hash);
+        Ok(())
+    }
+
+    pub fn remove_swap(&mut self) {
+        if let Some(file_name) = &self.file_name {
+            remove_swap_file(&sidecar_path(file_name, SWAP_SUFFIX));
+        }
+        self.swap_hash = None;
+    }
+
+    // The text of a swap file written by another session, along with that
+    // session's process ID if it is still running. A swap file whose session
+    // is gone and that holds nothing the file doesn't is just removed.
+    pub fn stale_swap(&mut self) -> Option<(String, Option<u32>)> {
+        let file_name = self.file_name.as_ref()?;
+        let contents = fs::read(sidecar_path(file_name, SWAP_SUFFIX)).ok()?;
+        let contents = String::from_utf8_lossy(&contents);
+        let (pid, text) = swap::parse(&contents);
+        if pid == Some(process::id()) {
+            return None;
+        }
+        let running = pid.filter(|pid| swap::is_running(*pid));
+        if running.is_none() && text == self.text() {
+            self.remove_swap();
+            return None;
+        }
+        Some((text.to_string(), running))
+    }
+
+    // The whole text with the document's line endings.
+    pub fn text(&self) -> String {
+        let ending = self.line_ending.as_str();
+        let mut text = String::new();
+        for (index, row) in self.rows.iter().enumerate() {
+            if index > 0 {
+                text.push_str(ending);
+            }
+            text.push_str(&String::from_utf8_lossy(row.as_bytes()));
+        }
+        if self.trailing_newline && !self.rows.is_empty() {
+            text.push_str(ending);
+        }
+        text
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    // Whether bytes that couldn't be decoded were replaced with U+FFFD.
+    pub fn is_lossy(&self) -> bool {
+        self.lossy
     }
-}
\ No newline at end of file
+
+    // Lets the next save replace the bytes that couldn't be decoded.
+    pub fn accept_lossy(&mut self) {
+        self.lossy = false;
+    }
+
+    pub fn has_bom(&self) -> bool {
+        self.bom
+    }
+
+    // Saves the document in `encoding` from now on. Nothing changes if the
+    // text can't be written in it.
+    pub fn save_as_encoding(&mut self, encoding: Encoding) -> Result<(), Error> {
+        let old = (self.encoding, self.bom);
+        self.encoding = encoding;
+        // A UTF-8 file keeps its byte order mark or lack of one.
+        if encoding != Encoding::Utf8 || old.0 != Encoding::Utf8 {
+            self.bom = encoding.wants_bom();
+        }
+        let result = self.save();
+        if result.is_err() {
+            (self.encoding, self.bom) = old;
+        }
+        result
+    }
+
+    // Replaces the whole text with `text`, as a single step that can be
+    // undone.
+    pub fn recover(&mut self, text: &str) {
+        let text = text.lines().collect::<Vec<&str>>().join("\n");
+        self.begin_undo_group();
+        if let Some(last) = self.rows.last() {
+            let end = Position {
+                x: last.len(),
+                y: self.rows.len().saturating_sub(1),
+            };
+            self.delete_range(&Position::default(), &end);
+        }
+        self.insert_str(&Position::default(), &text);
+        self.end_undo_group();
+    }
+
+    // Looks for `query` starting at `at`, walking rows in `direction`.
+    pub fn find(&self, query: &str, at: &Position, direction: SearchDirection) -> Option<Position> {
+        self.find_in_rows(at, direction, |row, x| row.find(query, x, direction).map(|x| (x, ())))
+            .map(|(position, ())| position)
+    }
+
+    // Brings the syntax highlighting of the rows before `until` up to date.
+    // Rows above `highlighted_until` are known to be current; below it, a
+    // row is only redone if it changed or the row above now ends differently.
+    pub fn highlight_syntax(&mut self, until: usize) {
+        let until = cmp::min(until, self.rows.len());
+        if until <= self.highlighted_until {
+            return;
+        }
+        let opts = self.file_type.highlighting_options();
+        let mut open = self
+            .highlighted_until
+            .checked_sub(1)
+            .and_then(|y| self.rows.get(y))
+            .map(Row::open_at_end)
+            .unwrap_or_default();
+        for row in self.rows.iter_mut().take(until).skip(self.highlighted_until) {
+            open = row.highlight_syntax(opts, &open);
+        }
+        self.highlighted_until = until;
+    }
+
+    // An edit in row `y` may change how every row from there on is colored.
+    fn invalidate_highlighting(&mut self, y: usize) {
+        self.highlighted_until = cmp::min(self.highlighted_until, y);
+    }
+
+    pub fn highlight(&mut self, word: Option<&str>) {
+        for row in &mut self.rows {
+            row.highlight(word);
+        }
+    }
+
+    // Regex flavour of `find`; also returns the match length in graphemes.
+    pub fn find_regex(&self, regex: &Regex, at: &Position, direction: SearchDirection) -> Option<(Position, usize)> {
+        self.find_in_rows(at, direction, |row, x| row.find_regex(regex, x, direction))
+    }
+
+    // Walks the rows from `at` in `direction` until `find_in_row` finds a
+    // match in a row, searching from the given column. The match's extra
+    // value, such as its length, is passed through.
+    #[allow(clippy::indexing_slicing)]
+    fn find_in_rows<T>(
+        &self,
+        at: &Position,
+        direction: SearchDirection,
+        mut find_in_row: impl FnMut(&Row, usize) -> Option<(usize, T)>,
+    ) -> Option<(Position, T)> {
+        if at.y >= self.rows.len() {
+            return None;
+        }
+        let mut position = Position { x: at.x, y: at.y };
+        let start = if direction == SearchDirection::Forward {
+            at.y
+        } else {
+            0
+        };
+        let end = if direction == SearchDirection::Forward {
+            self.rows.len()
+        } else {
+            at.y.saturating_add(1)
+        };
+        for _ in start..end {
+            if let Some(row) = self.rows.get(position.y) {
+                if let Some((x, found)) = find_in_row(row, position.x) {
+                    position.x = x;
+                    return Some((position, found));
+                }
+                if direction == SearchDirection::Forward {
+                    position.y = position.y.saturating_add(1);
+                    position.x = 0;
+                } else {
+                    position.y = position.y.saturating_sub(1);
+                    position.x = self.rows[position.y].len();
+                }
+            } else {
+                return None;
+            }
+        }
+        None
+    }
+
+    pub fn highlight_regex(&mut self, regex: Option<&Regex>) {
+        for row in &mut self.rows {
+            row.highlight_regex(regex);
+        }
+    }
+
+    pub fn highlight_match(&mut self, at: &Position, len: usize) {
+        self.highlight(None);
+        if let Some(row) = self.rows.get_mut(at.y) {
+            row.highlight_match(at.x, len);
+        }
+    }
+
+    // Replaces the regex match at `at`. The document only becomes dirty when
+    // the replacement actually differs from the matched text.
+    pub fn replace_regex(&mut self, regex: &Regex, at: &Position, replacement: &str) -> Option<usize> {
+        let row = self.rows.get_mut(at.y)?;
+        let (matched, inserted) = row.replace_regex(regex, at.x, replacement)?;
+        let len = inserted[..].graphemes(true).count();
+        if matched != inserted {
+            let after = Position {
+                x: at.x.saturating_add(len),
+                y: at.y,
+            };
+            self.history.begin_group();
+            self.record(Edit::Delete { at: *at, text: matched }, *at, *at);
+            self.record(Edit::Insert { at: *at, text: inserted }, *at, after);
+            self.history.end_group();
+        }
+        Some(len)
+    }
+}
+
+// `dir/name.rs` keeps its history in `dir/.name.rs.hecto-undo` and its
+// unsaved changes in `dir/.name.rs.hecto-swp`.
+fn sidecar_path(file_name: &str, suffix: &str) -> PathBuf {
+    let path = Path::new(file_name);
+    let name = path
+        .file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+    path.with_file_name(format!(".{name}.{suffix}"))
+}
+
+// Removes a swap file, unless another session that is still running wrote
+// it.
+fn remove_swap_file(path: &Path) {
+    if let Ok(contents) = fs::read(path) {
+        let (pid, _) = swap::parse(&String::from_utf8_lossy(&contents));
+        if pid.is_some_and(|pid| pid != process::id() && swap::is_running(pid)) {
+            return;
+        }
+    }
+    let _ = fs::remove_file(path);
+}
+
+// Writes a file only its owner can read, for the sidecar files that hold
+// text from the file being edited. An existing one with wider permissions
+// is narrowed before anything is written to it.
+fn write_private(path: &Path, contents: &[u8]) -> Result<(), Error> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(contents)
+}
+
+// Replaces the file at `path` with `contents` such that it ends up either
+// fully written or untouched: the new contents go to a temporary file next to
+// it, which is then renamed over it. A symlink is followed, so the file it
+// points to is replaced rather than the link.
+fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), Error> {
+    let target = follow_symlinks(path);
+    // Renaming would replace a file we may not write to, as long as the
+    // directory is writable. Opening it for writing, without truncating,
+    // asks the same question a plain save would have.
+    if target.exists() {
+        fs::OpenOptions::new().write(true).open(&target)?;
+    }
+    let directory = match target.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    let temp = directory.join(format!(".{name}.hecto-{}.tmp", process::id()));
+    let result = write_temp(&temp, &target, contents).and_then(|()| fs::rename(&temp, &target));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+        return result;
+    }
+    // Makes the rename itself survive a crash. Not every file system lets a
+    // directory be synced, and the file is saved either way.
+    if let Ok(directory) = fs::File::open(&directory) {
+        let _ = directory.sync_all();
+    }
+    Ok(())
+}
+
+// The file `path` leads to through any symlinks, even if it doesn't exist
+// yet. Unlike `fs::canonicalize`, this works for a link whose target is
+// missing, which saving then creates.
+fn follow_symlinks(path: &Path) -> PathBuf {
+    let mut target = path.to_path_buf();
+    // The kernel's limit, in case links point at each other.
+    for _ in 0..40 {
+        let is_link = fs::symlink_metadata(&target).is_ok_and(|metadata| metadata.file_type().is_symlink());
+        if !is_link {
+            break;
+        }
+        let Ok(link) = fs::read_link(&target) else {
+            break;
+        };
+        // A relative link is relative to the directory it is in.
+        target = match target.parent() {
+            Some(parent) => parent.join(link),
+            None => link,
+        };
+    }
+    target
+}
+
+// Writes and syncs the temporary file, giving it the permissions and owner
+// of `original` if that exists already. They are set before anything is
+// written, so a private file's contents are never readable by others.
+fn write_temp(temp: &Path, original: &Path, contents: &[u8]) -> Result<(), Error> {
+    let mut file = fs::OpenOptions::new().write(true).create_new(true).open(temp)?;
+    if let Ok(metadata) = fs::metadata(original) {
+        file.set_permissions(metadata.permissions())?;
+        // Only root may give a file away, so for anyone else this fails
+        // unless they own the original already; the file is still saved.
+        let _ = chown(temp, Some(metadata.uid()), Some(metadata.gid()));
+    }
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    // A directory of its own for each test, removed again afterwards.
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("hecto-test-{}-{name}", process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn path(&self, name: &str) -> PathBuf {
+            self.0.join(name)
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn document(text: &str) -> Document {
+        Document::from_text(text, &Config::default())
+    }
+
+    fn at(x: usize, y: usize) -> Position {
+        Position { x, y }
+    }
+
+    #[test]
+    fn insert_str_round_trips_through_undo() {
+        let mut doc = document("one\ntwo\n");
+        let end = doc.insert_str(&at(1, 0), "X\nnew\nY");
+        assert!(end == at(1, 2));
+        assert_eq!(doc.text(), "oX\nnew\nYne\ntwo\n");
+        assert!(doc.undo() == Some(at(1, 0)));
+        assert_eq!(doc.text(), "one\ntwo\n");
+        assert!(!doc.is_dirty());
+        assert!(doc.redo() == Some(at(1, 2)));
+        assert_eq!(doc.text(), "oX\nnew\nYne\ntwo\n");
+        assert!(doc.is_dirty());
+    }
+
+    #[test]
+    fn delete_range_round_trips_through_undo() {
+        let mut doc = document("one\ntwo\nthree\nfour\n");
+        assert_eq!(doc.delete_range(&at(1, 0), &at(2, 2)), "ne\ntwo\nth");
+        assert_eq!(doc.text(), "oree\nfour\n");
+        doc.undo();
+        assert_eq!(doc.text(), "one\ntwo\nthree\nfour\n");
+        doc.redo();
+        assert_eq!(doc.text(), "oree\nfour\n");
+        // An end past the last row stops at the end of the document.
+        assert_eq!(doc.delete_range(&at(2, 0), &at(0, 9)), "ee\nfour");
+        assert_eq!(doc.text(), "or\n");
+        doc.undo();
+        doc.undo();
+        assert_eq!(doc.text(), "one\ntwo\nthree\nfour\n");
+        assert!(!doc.is_dirty());
+    }
+
+    #[test]
+    fn newlines_and_joins_undo() {
+        let mut doc = document("ab\ncd\n");
+        doc.insert(&at(1, 0), '\n');
+        assert_eq!(doc.text(), "a\nb\ncd\n");
+        doc.delete(&at(1, 1));
+        assert_eq!(doc.text(), "a\nbcd\n");
+        doc.undo();
+        assert_eq!(doc.text(), "a\nb\ncd\n");
+        doc.undo();
+        assert_eq!(doc.text(), "ab\ncd\n");
+        assert!(doc.undo().is_none());
+    }
+
+    #[test]
+    fn editing_after_undo_starts_a_branch() {
+        let mut doc = document("\n");
+        doc.insert_str(&at(0, 0), "first");
+        doc.undo();
+        doc.insert_str(&at(0, 0), "second");
+        assert!(doc.redo().is_none());
+        doc.undo();
+        assert_eq!(doc.text(), "\n");
+        // Time travel reaches the branch that was undone.
+        assert!(doc.travel(true).is_some());
+        assert_eq!(doc.text(), "first\n");
+        assert!(doc.travel(true).is_some());
+        assert_eq!(doc.text(), "second\n");
+    }
+
+    #[test]
+    fn text_range_spans_rows() {
+        let doc = document("one\ntwo\nthree\n");
+        assert_eq!(doc.text_range(&at(1, 0), &at(2, 2)), "ne\ntwo\nth");
+        assert_eq!(doc.text_range(&at(1, 1), &at(3, 1)), "wo");
+        assert_eq!(doc.text_range(&at(3, 0), &at(0, 1)), "\n");
+        // Rows past the end contribute nothing.
+        assert_eq!(doc.text_range(&at(2, 2), &at(0, 9)), "ree");
+    }
+
+    #[test]
+    fn replacing_a_selection_undoes_in_one_step() {
+        let mut doc = document("one two\nthree\n");
+        doc.begin_undo_group();
+        doc.delete_range(&at(4, 0), &at(2, 1));
+        doc.insert_str(&at(4, 0), "pasted\ntext");
+        doc.end_undo_group();
+        assert_eq!(doc.text(), "one pasted\ntextree\n");
+        doc.undo();
+        assert_eq!(doc.text(), "one two\nthree\n");
+        assert!(!doc.is_dirty());
+    }
+
+    #[test]
+    fn typing_past_the_last_row_is_one_step() {
+        let mut doc = document("first\n");
+        for (x, c) in "new row".chars().enumerate() {
+            doc.insert(&at(x, 1), c);
+        }
+        assert_eq!(doc.text(), "first\nnew row\n");
+        doc.undo();
+        assert_eq!(doc.text(), "first\nnew \n");
+        doc.undo();
+        assert_eq!(doc.text(), "first\n");
+        assert!(doc.undo().is_none());
+    }
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[test]
+    fn find_walks_the_rows_both_ways() {
+        let document = document("one two\nthree\ntwo too");
+        let forward = SearchDirection::Forward;
+        let backward = SearchDirection::Backward;
+        assert!(document.find("two", &at(0, 0), forward) == Some(at(4, 0)));
+        assert!(document.find("two", &at(5, 0), forward) == Some(at(0, 2)));
+        assert!(document.find("two", &at(3, 2), backward) == Some(at(0, 2)));
+        assert!(document.find("two", &at(2, 2), backward) == Some(at(4, 0)));
+        assert!(document.find("two", &at(0, 1), backward) == Some(at(4, 0)));
+        assert!(document.find("four", &at(0, 0), forward).is_none());
+        assert!(document.find("two", &at(0, 3), forward).is_none());
+        let regex = Regex::new("t[wo]+").unwrap();
+        assert!(document.find_regex(&regex, &at(5, 0), forward) == Some((at(0, 2), 3)));
+        assert!(document.find_regex(&regex, &at(1, 2), forward) == Some((at(4, 2), 3)));
+        assert!(document.find_regex(&regex, &at(0, 2), backward) == Some((at(4, 0), 3)));
+    }
+
+    // Opens a file holding `bytes` in `scratch`.
+    fn open_bytes(scratch: &Scratch, bytes: &[u8]) -> Document {
+        let file = scratch.path("file.txt");
+        fs::write(&file, bytes).unwrap();
+        Document::open(file.to_str().unwrap(), &FileTypes::default(), &Config::default()).unwrap()
+    }
+
+    #[test]
+    fn most_lines_decide_the_line_ending() {
+        let scratch = Scratch::new("line-endings");
+        assert!(open_bytes(&scratch, b"a\r\nb\r\nc\n").line_ending() == LineEnding::CrLf);
+        assert!(open_bytes(&scratch, b"a\r\nb\nc\n").line_ending() == LineEnding::Lf);
+        // A tie goes to LF.
+        assert!(open_bytes(&scratch, b"a\r\nb\n").line_ending() == LineEnding::Lf);
+        assert!(open_bytes(&scratch, b"a").line_ending() == LineEnding::Lf);
+        // Mixed files are saved with the majority's ending throughout.
+        let doc = open_bytes(&scratch, b"a\r\nb\r\nc\n");
+        assert_eq!(doc.contents().unwrap(), b"a\r\nb\r\nc\r\n");
+    }
+
+    #[test]
+    fn files_round_trip_unchanged() {
+        let scratch = Scratch::new("round-trip");
+        for bytes in [
+            &b""[..],
+            b"\n",
+            b"no newline",
+            b"two\nlines",
+            b"two\r\nlines",
+            b"crlf\r\n",
+            b"\xef\xbb\xbfbom\n",
+            b"\xff\xfeu\x00\r\x00\n\x00",
+        ] {
+            let doc = open_bytes(&scratch, bytes);
+            assert_eq!(doc.contents().unwrap(), bytes, "{bytes:?}");
+            assert!(!doc.is_dirty());
+        }
+    }
+
+    #[test]
+    fn an_empty_file_gets_a_final_newline() {
+        let scratch = Scratch::new("empty");
+        let mut doc = open_bytes(&scratch, b"");
+        doc.insert(&at(0, 0), 'a');
+        assert_eq!(doc.contents().unwrap(), b"a\n");
+    }
+
+    #[test]
+    fn changing_the_line_ending_is_a_change() {
+        let scratch = Scratch::new("set-line-ending");
+        let mut doc = open_bytes(&scratch, b"a\nb\n");
+        doc.set_line_ending(LineEnding::CrLf);
+        assert!(doc.is_dirty());
+        doc.set_line_ending(LineEnding::Lf);
+        assert!(!doc.is_dirty());
+        doc.set_line_ending(LineEnding::CrLf);
+        doc.save().unwrap();
+        assert!(!doc.is_dirty());
+        assert_eq!(fs::read(scratch.path("file.txt")).unwrap(), b"a\r\nb\r\n");
+        // Going back is a change again once the file has CRLF.
+        doc.set_line_ending(LineEnding::Lf);
+        assert!(doc.is_dirty());
+    }
+
+    #[test]
+    fn save_replaces_the_contents() {
+        let scratch = Scratch::new("replace");
+        let file = scratch.path("file.txt");
+        fs::write(&file, "old contents that are longer\n").unwrap();
+        write_atomically(&file, b"new\n").unwrap();
+        assert_eq!(fs::read(&file).unwrap(), b"new\n");
+        // No temporary file is left behind.
+        assert_eq!(fs::read_dir(&scratch.0).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn save_keeps_the_file_mode() {
+        let scratch = Scratch::new("mode");
+        let file = scratch.path("script.sh");
+        fs::write(&file, "old").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o751)).unwrap();
+        write_atomically(&file, b"new").unwrap();
+        assert_eq!(mode(&file), 0o751);
+    }
+
+    #[test]
+    fn save_writes_through_a_symlink() {
+        let scratch = Scratch::new("symlink");
+        let file = scratch.path("real.txt");
+        let link = scratch.path("link.txt");
+        fs::write(&file, "old").unwrap();
+        symlink("real.txt", &link).unwrap();
+        write_atomically(&link, b"new").unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read(&file).unwrap(), b"new");
+    }
+
+    #[test]
+    fn save_creates_the_target_of_a_dangling_symlink() {
+        let scratch = Scratch::new("dangling");
+        let link = scratch.path("link.txt");
+        symlink(scratch.path("missing.txt"), &link).unwrap();
+        write_atomically(&link, b"new").unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read(scratch.path("missing.txt")).unwrap(), b"new");
+    }
+
+    #[test]
+    fn save_follows_chains_of_relative_symlinks() {
+        let scratch = Scratch::new("chain");
+        fs::create_dir(scratch.path("sub")).unwrap();
+        fs::write(scratch.path("sub/real.txt"), "old").unwrap();
+        symlink("real.txt", scratch.path("sub/inner.txt")).unwrap();
+        symlink("sub/inner.txt", scratch.path("outer.txt")).unwrap();
+        write_atomically(&scratch.path("outer.txt"), b"new").unwrap();
+        assert_eq!(fs::read(scratch.path("sub/real.txt")).unwrap(), b"new");
+        assert!(fs::symlink_metadata(scratch.path("sub/inner.txt")).unwrap().file_type().is_symlink());
+    }
+
+    #[test]
+    fn save_into_a_missing_directory_fails() {
+        let scratch = Scratch::new("no-directory");
+        assert!(write_atomically(&scratch.path("gone/file.txt"), b"new").is_err());
+        assert_eq!(fs::read_dir(&scratch.0).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn saving_a_document_clears_dirty_and_the_swap_file() {
+        let scratch = Scratch::new("document");
+        let file = scratch.path("notes.txt");
+        fs::write(&file, "one\n").unwrap();
+        let name = file.to_str().unwrap();
+        let mut doc = Document::open(name, &FileTypes::default(), &Config::default()).unwrap();
+        doc.insert_str(&at(3, 0), " two");
+        assert!(doc.is_dirty());
+        let swap = sidecar_path(name, SWAP_SUFFIX);
+        fs::write(&swap, swap::contents(&doc.text())).unwrap();
+        doc.save().unwrap();
+        assert!(!doc.is_dirty());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "one two\n");
+        assert!(!swap.exists());
+    }
+
+    #[test]
+    fn save_refuses_a_read_only_file() {
+        let scratch = Scratch::new("read-only");
+        let file = scratch.path("locked.txt");
+        fs::write(&file, "old").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o444)).unwrap();
+        // Permissions don't stop root, so there's nothing to check then.
+        if fs::OpenOptions::new().write(true).open(&file).is_ok() {
+            return;
+        }
+        assert!(write_atomically(&file, b"new").is_err());
+        assert_eq!(fs::read(&file).unwrap(), b"old");
+        assert_eq!(fs::read_dir(&scratch.0).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn save_asks_before_replacing_undecodable_bytes() {
+        let scratch = Scratch::new("lossy");
+        let file = scratch.path("broken.txt");
+        fs::write(&file, b"\xef\xbb\xbfa\xffb\n").unwrap();
+        let name = file.to_str().unwrap();
+        let mut document = Document::open(name, &FileTypes::default(), &Config::default()).unwrap();
+        assert!(document.is_lossy());
+        assert!(document.save().is_err());
+        assert_eq!(fs::read(&file).unwrap(), b"\xef\xbb\xbfa\xffb\n");
+        document.accept_lossy();
+        document.save().unwrap();
+        assert_eq!(fs::read(&file).unwrap(), "\u{feff}a\u{fffd}b\n".as_bytes());
+    }
+}
@@ -1,18 +1,46 @@
+use crate::clipboard::Clipboard;
+use crate::Buffer;
+use crate::Config;
+use crate::config::{LineNumbers, Wrap};
 use crate::Document;
+use crate::document::LineEnding;
+use crate::encoding::Encoding;
+use crate::FileTypes;
+use crate::keymap::{self, Command, Keymap, Lookup};
 use crate::Row;
+use crate::row::{text_width, truncate_to_width};
+use crate::swap;
+use crate::terminal::Event;
 use crate::Terminal;
-use std::env;
+use crate::Theme;
+use crate::vim::{self, Action, InsertAt, Mode, Operator, Vim};
+use crate::window::{Direction, Layout, Rect, View};
+use regex::Regex;
+use std::cmp;
+use std::io::ErrorKind;
+use std::mem;
+use std::ops::Range;
 use std::time::Duration;
 use std::time::Instant;
-use termion::color;
 use termion::event::Key;
+use termion::style;
 
-const STATUS_FG_COLOR: color::Rgb = color::Rgb(63, 63, 63);
-const STATUS_BG_COLOR: color::Rgb = color::Rgb(239, 239, 239);
 const VERSION: &str = env!("CARGO_PKG_VERSION");
-const QUIT_TIMES: u8 = 2;
+// Starts the screen lines that continue a wrapped row.
+const WRAP_INDICATOR: &str = "↪";
+// How long to wait for input before doing background work such as writing
+// swap files.
+const INPUT_TIMEOUT: Duration = Duration::from_secs(1);
+// How many percent of a split one grow_pane or shrink_pane moves.
+const PANE_RESIZE_STEP: isize = 5;
 
-#[derive(Default)]
+#[derive(PartialEq, Clone, Copy)]
+pub enum SearchDirection {
+    Forward,
+    Backward,
+}
+
+#[derive(Default, Clone, Copy, PartialEq)]
 pub struct Position {
     // and not u16 because that's too small. we want to take into account large documents.
     // usize depends on the machine's architecture.
@@ -47,6 +75,46 @@ pub struct Editor {
     document: Document,
     status_message: StatusMessage,
     quit_times: u8,
+
+    // The other end of the selection; the cursor is always the moving end.
+    mark: Option<Position>,
+    // Whether the mark was set by a shift+arrow, in which case plain
+    // movement drops the selection again.
+    shift_selection: bool,
+    clipboard: Clipboard,
+    file_types: FileTypes,
+    theme: Theme,
+    config: Config,
+    keymap: Keymap,
+    // The keys of a multi-key binding such as Ctrl-X Ctrl-S typed so far.
+    pending_keys: Vec<Key>,
+    // Set when the `vim` preset turns on modal editing.
+    vim: Option<Vim>,
+    // The command run by the last key press, for commands such as kill_line
+    // that behave differently when repeated.
+    previous_command: Option<Command>,
+    // Where the last paste put its text, for yank_pop to replace it.
+    last_yank: Option<(Position, Position)>,
+    // When the swap files were last brought up to date.
+    last_swap: Instant,
+    // When the last key was pressed, to tell when the editor is idle.
+    last_input: Instant,
+    // When autosave last saved something, and why it failed if it did.
+    last_autosave: Option<(Instant, Option<String>)>,
+    // Every open file. The entry at `current_buffer` is an empty placeholder
+    // while its document is the one being edited.
+    buffers: Vec<Buffer>,
+    current_buffer: usize,
+    // Every pane's view. Like buffers, the entry at `current_view` is out of
+    // date while its cursor and offset live in the editor.
+    views: Vec<View>,
+    current_view: usize,
+    layout: Layout,
+    // Starts out as configured; toggle_line_numbers cycles through the modes.
+    line_numbers: LineNumbers,
+    // While wrapping there is no scrolling sideways, so `offset.x` instead
+    // counts the screen lines of the row at `offset.y` scrolled off the top.
+    wrap: Wrap,
 }
 
 
@@ -54,6 +122,10 @@ impl Editor {
     // Called in main to start the editor. Whil this is alive,
     // we are inside the editor.
     pub fn run(&mut self) {
+        if self.config.autosave_on_focus_loss {
+            Terminal::report_focus();
+        }
+        self.check_swap_files();
         // keeps the editor running until we quit.
         loop {
             if let Err(error) = self.refresh_screen() {
@@ -62,32 +134,56 @@ impl Editor {
 
             // Set when we press Ctrl+Q.
             if self.should_quit {
+                self.remove_swap_files();
                 break;
             }
             if let Err(error) = self.process_keypress() {
                 die(error);
             }
+            self.update_swap_files();
+            self.autosave_when_idle();
         }
     }
 
     // Instantiated in main to get a handle to the editor. then we call
     // run().
     pub fn default() -> Self {
-        let args: Vec<String> = std::env::args().collect();
-        let mut initial_status = String::from("HELP: Ctrl-Q = quit");
-        let document = if args.len() > 1 {
-            let file_name = &args[1]; // args[0] is the name of the program.
-            let doc = Document::open(&file_name);
-            if doc.is_ok() {
-                doc.unwrap()
-            } else {
-                initial_status = format!("ERR: Could not open file: {}", file_name);
-                Document::default()
+        let (config_path, file_names) = parse_args(std::env::args().skip(1)); // args[0] is the name of the program.
+        let (config, config_errors) = Config::load(config_path.as_deref());
+        let (keymap, keymap_errors) = Keymap::load(&config.preset, &config.keys);
+        let (file_types, syntax_errors) = FileTypes::load();
+        // Everything that went wrong while starting up, most important first.
+        let mut errors = Vec::new();
+        let mut buffers = Vec::new();
+        for file_name in file_names {
+            match Document::open(&file_name, &file_types, &config) {
+                Ok(document) => {
+                    errors.extend(lossy_warning(&document));
+                    buffers.push(Buffer::from(document));
+                }
+                Err(_) => errors.push(format!("Could not open file: {}", file_name)),
             }
-        } else {
-            Document::default()
+        }
+        if buffers.is_empty() {
+            buffers.push(Buffer::from(Document::new(&config)));
+        }
+        // The first buffer starts out active, so its document moves into the
+        // editor.
+        let document = mem::take(&mut buffers[0].document);
+        let (theme, theme_error) = Theme::load(config.theme.as_deref(), Terminal::color_mode());
+        let vim = (config.preset == "vim").then(Vim::default);
+        errors.extend(config_errors);
+        errors.extend(keymap_errors);
+        errors.extend(theme_error);
+        errors.extend(syntax_errors.into_iter().map(|error| format!("Bad syntax definition {error}")));
+        let initial_status = match errors.first() {
+            Some(error) if errors.len() > 1 => format!("ERR: {error} (and {} more)", errors.len() - 1),
+            Some(error) => format!("ERR: {error}"),
+            None => help(&keymap),
         };
 
+        let line_numbers = config.line_numbers;
+        let wrap = config.wrap;
         Self {
             should_quit: false,
             terminal: Terminal::default().expect("Failed to initialize terminal"),
@@ -95,24 +191,57 @@ impl Editor {
             cursor_position: Position::default(),
             offset: Position::default(),
             status_message: StatusMessage::from(initial_status),
-            quit_times: QUIT_TIMES,
+            quit_times: config.quit_times,
+            mark: None,
+            shift_selection: false,
+            clipboard: Clipboard::from_config(&config.clipboard),
+            file_types,
+            theme,
+            config,
+            keymap,
+            pending_keys: Vec::new(),
+            vim,
+            previous_command: None,
+            last_yank: None,
+            last_swap: Instant::now(),
+            last_input: Instant::now(),
+            last_autosave: None,
+            buffers,
+            current_buffer: 0,
+            views: vec![View::default()],
+            current_view: 0,
+            layout: Layout::Pane(0),
+            line_numbers,
+            wrap,
         }
     }
 
-    fn refresh_screen(&self) -> Result<(), std::io::Error> {
+    fn refresh_screen(&mut self) -> Result<(), std::io::Error> {
         Terminal::cursor_hide();
         Terminal::cursor_position(&Position::default());
         if self.should_quit {
             Terminal::clear_screen();
             println!("Keep planting. Goodbye.\r");
         } else {
-            self.draw_rows();
-            self.draw_status_bar();
+            let mut panes = self.layout.panes(self.screen());
+            for (index, rect) in &panes {
+                let view = self.view(*index);
+                self.buffer_document_mut(view.buffer)
+                    .highlight_syntax(view.offset.y.saturating_add(rect.height));
+            }
+            // Every pane line is cleared up to the right edge of the screen,
+            // so panes further right have to be drawn after it.
+            panes.sort_by_key(|(_, rect)| rect.x);
+            for (index, rect) in &panes {
+                self.draw_pane(*index, *rect);
+            }
             self.draw_message_bar();
+            let rect = self.pane_rect(self.current_view);
+            let gutter = self.gutter_width(&self.document);
+            let cursor = self.cursor_on_screen();
             Terminal::cursor_position(&Position {
-                // saturation means we go beyond the allowed value for the type.
-                x: self.cursor_position.x.saturating_sub(self.offset.x),
-                y: self.cursor_position.y.saturating_sub(self.offset.y),
+                x: rect.x.saturating_add(gutter).saturating_add(cursor.x),
+                y: rect.y.saturating_add(cursor.y),
             });
         }
         Terminal::cursor_show();
@@ -121,80 +250,1359 @@ impl Editor {
 
     fn save(&mut self) {
         if self.document.file_name.is_none() {
-            let new_name = self.prompt("Save as: ").unwrap_or(None);
+            let new_name = self.prompt("Save as: ", |_, _, _| {}).unwrap_or(None);
             if new_name.is_none() {
                 self.status_message = StatusMessage::from("Save aborted".to_string());
                 return;
             }
+            if let Some(name) = &new_name {
+                self.document.set_file_type(self.file_types.detect(name));
+            }
             self.document.file_name = new_name;
         }
-        if self.document.save().is_ok() {
-            self.status_message = StatusMessage::from("File saved successfully".to_string());
+        if !self.confirm_lossy_save() {
+            self.status_message = StatusMessage::from("Save aborted".to_string());
+            return;
+        }
+        match self.document.save() {
+            Ok(()) => self.status_message = StatusMessage::from("File saved successfully".to_string()),
+            Err(error) => self.status_message = StatusMessage::from(format!("Error writing file: {error}")),
+        }
+    }
+
+    fn save_as_encoding(&mut self) {
+        if self.document.file_name.is_none() {
+            self.status_message = StatusMessage::from("Save the file first".to_string());
+            return;
+        }
+        let question = format!("Save as encoding ({}): ", Encoding::names().join(", "));
+        let Some(name) = self.prompt(&question, |_, _, _| {}).unwrap_or(None) else {
+            self.status_message = StatusMessage::from("Save aborted".to_string());
+            return;
+        };
+        let Some(encoding) = Encoding::from_name(&name) else {
+            self.status_message = StatusMessage::from(format!("Unknown encoding: {name}"));
+            return;
+        };
+        if !self.confirm_lossy_save() {
+            self.status_message = StatusMessage::from("Save aborted".to_string());
+            return;
+        }
+        match self.document.save_as_encoding(encoding) {
+            Ok(()) => {
+                self.status_message = StatusMessage::from(format!("File saved as {}", encoding.name()));
+            }
+            Err(error) => self.status_message = StatusMessage::from(format!("Error writing file: {error}")),
+        }
+    }
+
+    // Saving a file that had undecodable bytes writes U+FFFD in their
+    // place, so the user has to agree to that first.
+    fn confirm_lossy_save(&mut self) -> bool {
+        if !self.document.is_lossy() {
+            return true;
+        }
+        let answer = self
+            .prompt("Undecodable bytes will be saved as U+FFFD. Save anyway? (y/n): ", |_, _, _| {})
+            .unwrap_or(None);
+        if answer.as_deref() != Some("y") {
+            return false;
+        }
+        self.document.accept_lossy();
+        true
+    }
+
+    // Incremental search: the cursor follows the query as it is typed and
+    // jumps back to where it was if the search is cancelled. With `use_regex`
+    // the query is treated as a regular expression.
+    fn search(&mut self, use_regex: bool) {
+        let old_position = self.cursor_position;
+        let mut direction = SearchDirection::Forward;
+        let prompt = if use_regex {
+            "Regex search (ESC to cancel, Arrows to navigate): "
+        } else {
+            "Search (ESC to cancel, Arrows to navigate): "
+        };
+        let query = self
+            .prompt(prompt, |editor, key, query| {
+                let mut moved = false;
+                match key {
+                    Key::Right | Key::Down => {
+                        direction = SearchDirection::Forward;
+                        editor.move_cursor(Key::Right);
+                        moved = true;
+                    }
+                    Key::Left | Key::Up => direction = SearchDirection::Backward,
+                    _ => direction = SearchDirection::Forward,
+                }
+                let found = if use_regex {
+                    // An incomplete pattern is expected while typing, so an
+                    // invalid regex simply matches nothing.
+                    let regex = Regex::new(query).ok();
+                    editor.document.highlight_regex(regex.as_ref());
+                    regex.and_then(|regex| {
+                        editor
+                            .document
+                            .find_regex(&regex, &editor.cursor_position, direction)
+                            .map(|(position, _)| position)
+                    })
+                } else {
+                    editor.document.highlight(Some(query));
+                    editor
+                        .document
+                        .find(query, &editor.cursor_position, direction)
+                };
+                if let Some(position) = found {
+                    editor.cursor_position = position;
+                    editor.scroll();
+                } else if moved {
+                    editor.move_cursor(Key::Left);
+                }
+            })
+            .unwrap_or(None);
+
+        if query.is_none() {
+            self.cursor_position = old_position;
+            self.scroll();
+        }
+        self.document.highlight(None);
+    }
+
+    // Regex search and replace over the whole document, asking for
+    // confirmation before each replacement.
+    fn replace(&mut self) {
+        let pattern = self.prompt("Replace regex: ", |_, _, _| {}).unwrap_or(None);
+        let Some(pattern) = pattern else {
+            self.status_message = StatusMessage::from("Replace aborted".to_string());
+            return;
+        };
+        let regex = match Regex::new(&pattern) {
+            Ok(regex) => regex,
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!("Invalid regex: {error}"));
+                return;
+            }
+        };
+        // An empty replacement is valid (it deletes the matches), so only ESC cancels.
+        let replacement = self.prompt_allow_empty("Replace with: ", |_, _, _| {}).unwrap_or(None);
+        let Some(replacement) = replacement else {
+            self.status_message = StatusMessage::from("Replace aborted".to_string());
+            return;
+        };
+
+        let old_position = self.cursor_position;
+        // The whole replace session is undone in one step.
+        self.document.begin_undo_group();
+        let mut position = Position::default();
+        let mut last_replaced = None;
+        let mut replaced: usize = 0;
+        let mut replace_all = false;
+        while let Some((found, len)) =
+            self.document
+                .find_regex(&regex, &position, SearchDirection::Forward)
+        {
+            let mut confirmed = replace_all;
+            if !replace_all {
+                self.cursor_position = found;
+                self.scroll();
+                self.document.highlight_match(&found, len);
+                self.status_message =
+                    StatusMessage::from("Replace this one? (y/n/a/q)".to_string());
+                if let Err(error) = self.refresh_screen() {
+                    die(error);
+                }
+                match self.terminal.read_key() {
+                    Ok(Key::Char('y')) => confirmed = true,
+                    Ok(Key::Char('n')) => (),
+                    Ok(Key::Char('a')) => {
+                        replace_all = true;
+                        confirmed = true;
+                    }
+                    Ok(Key::Char('q') | Key::Esc) => break,
+                    Ok(_) => continue,
+                    Err(error) => die(error),
+                }
+            }
+            let mut skip = len;
+            if confirmed {
+                if let Some(inserted) = self.document.replace_regex(&regex, &found, &replacement) {
+                    skip = inserted;
+                    replaced = replaced.saturating_add(1);
+                    last_replaced = Some(found);
+                }
+            }
+            // An empty match that was left alone or replaced with nothing
+            // would be found again, so step past it.
+            if skip == 0 && len == 0 {
+                skip = 1;
+            }
+            position = Position {
+                x: found.x.saturating_add(skip),
+                y: found.y,
+            };
+        }
+        self.document.end_undo_group();
+        self.document.highlight(None);
+        self.cursor_position = last_replaced.unwrap_or(old_position);
+        self.scroll();
+        self.status_message = StatusMessage::from(format!("Replaced {replaced} occurrence(s)"));
+    }
+
+    fn undo(&mut self) {
+        if let Some(position) = self.document.undo() {
+            self.cursor_position = position;
+        } else {
+            self.status_message = StatusMessage::from("Nothing to undo".to_string());
+        }
+    }
+
+    fn redo(&mut self) {
+        if let Some(position) = self.document.redo() {
+            self.cursor_position = position;
+        } else {
+            self.status_message = StatusMessage::from("Nothing to redo".to_string());
+        }
+    }
+
+    fn travel(&mut self, forward: bool) {
+        if let Some(position) = self.document.travel(forward) {
+            self.cursor_position = position;
+            let (state, states) = self.document.history_state();
+            self.status_message = StatusMessage::from(format!("History: state {state} of {states}"));
+        } else if forward {
+            self.status_message = StatusMessage::from("Already at the newest state".to_string());
+        } else {
+            self.status_message = StatusMessage::from("Already at the oldest state".to_string());
+        }
+    }
+
+    // The selected range, ordered from start to end. Empty selections
+    // count as no selection.
+    fn selection(&self) -> Option<(Position, Position)> {
+        if self.vim.as_ref().is_some_and(|vim| vim.mode() == Mode::Visual) {
+            return self.visual_selection();
+        }
+        let mark = self.mark?;
+        if mark == self.cursor_position {
+            return None;
+        }
+        Some(ordered(mark, self.cursor_position))
+    }
+
+    // Vim's visual selection includes the character under the cursor.
+    fn visual_selection(&self) -> Option<(Position, Position)> {
+        let (start, mut end) = ordered(self.mark?, self.cursor_position);
+        end.x = cmp::min(end.x.saturating_add(1), self.row_len(end.y));
+        if start == end {
+            return None;
+        }
+        Some((start, end))
+    }
+
+    fn toggle_mark(&mut self) {
+        if self.mark.is_some() && !self.shift_selection {
+            self.mark = None;
+            self.status_message = StatusMessage::from("Mark cleared".to_string());
+        } else {
+            self.mark = Some(self.cursor_position);
+            self.shift_selection = false;
+            self.status_message = StatusMessage::from("Mark set".to_string());
+        }
+    }
+
+    // Deletes the selected text, if any, and tells whether there was any.
+    fn delete_selection(&mut self) -> bool {
+        let selection = self.selection();
+        self.mark = None;
+        if let Some((start, end)) = selection {
+            self.document.delete_range(&start, &end);
+            self.cursor_position = start;
+            return true;
+        }
+        false
+    }
+
+    fn copy(&mut self) {
+        if let Some((start, end)) = self.selection() {
+            let text = self.document.text_range(&start, &end);
+            self.mark = None;
+            self.status_message = match self.clipboard.copy(text) {
+                Ok(()) => StatusMessage::from("Copied selection".to_string()),
+                Err(error) => StatusMessage::from(format!("Copied, but clipboard failed: {error}")),
+            };
+        } else {
+            self.status_message = StatusMessage::from("Nothing selected".to_string());
+        }
+    }
+
+    fn cut(&mut self) {
+        if let Some((start, end)) = self.selection() {
+            let text = self.document.delete_range(&start, &end);
+            self.cursor_position = start;
+            self.mark = None;
+            self.status_message = match self.clipboard.copy(text) {
+                Ok(()) => StatusMessage::from("Cut selection".to_string()),
+                Err(error) => StatusMessage::from(format!("Cut, but clipboard failed: {error}")),
+            };
+        } else {
+            self.status_message = StatusMessage::from("Nothing selected".to_string());
+        }
+    }
+
+    fn paste(&mut self) {
+        let text = match self.clipboard.paste() {
+            Ok(text) => text,
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!("Paste failed: {error}"));
+                return;
+            }
+        };
+        if text.is_empty() {
+            return;
+        }
+        let start = self.paste_text(&text);
+        self.last_yank = Some((start, self.cursor_position));
+    }
+
+    // Inserts pasted text as one edit, replacing the selection if there is
+    // one, and returns where the text starts.
+    fn paste_text(&mut self, text: &str) -> Position {
+        // Terminals send pasted line breaks as carriage returns.
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        // Replacing the selection is undone in one step.
+        self.document.begin_undo_group();
+        self.delete_selection();
+        let start = self.cursor_position;
+        self.cursor_position = self.document.insert_str(&start, &text);
+        self.document.end_undo_group();
+        start
+    }
+
+    // Makes buffer `index` the one being edited, keeping the current one's
+    // cursor and scroll position for when it comes back.
+    fn switch_buffer(&mut self, index: usize) {
+        if index == self.current_buffer || index >= self.buffers.len() {
+            return;
+        }
+        let parked = &mut self.buffers[self.current_buffer];
+        mem::swap(&mut parked.document, &mut self.document);
+        parked.cursor_position = self.cursor_position;
+        parked.offset = self.offset;
+        parked.mark = self.mark.take();
+        self.current_buffer = index;
+        let active = &mut self.buffers[index];
+        mem::swap(&mut active.document, &mut self.document);
+        self.cursor_position = active.cursor_position;
+        self.offset = active.offset;
+        self.mark = active.mark.take();
+        self.shift_selection = false;
+        self.last_yank = None;
+    }
+
+    fn cycle_buffer(&mut self, forward: bool) {
+        let count = self.buffers.len();
+        if count < 2 {
+            self.status_message = StatusMessage::from("No other buffers".to_string());
+            return;
+        }
+        let index = if forward {
+            self.current_buffer.saturating_add(1) % count
+        } else {
+            self.current_buffer.checked_sub(1).unwrap_or(count.saturating_sub(1))
+        };
+        self.switch_buffer(index);
+        self.list_buffers();
+    }
+
+    // Opens a file in a new buffer, or switches to it if it's already open.
+    fn open_file(&mut self) {
+        let Some(file_name) = self.prompt("Open: ", |_, _, _| {}).unwrap_or(None) else {
+            self.status_message = StatusMessage::from("Open aborted".to_string());
+            return;
+        };
+        let open = (0..self.buffers.len()).find(|index| {
+            self.buffer_document(*index).file_name.as_deref() == Some(file_name.as_str())
+        });
+        if let Some(index) = open {
+            self.switch_buffer(index);
+            return;
+        }
+        let document = match Document::open(&file_name, &self.file_types, &self.config) {
+            Ok(document) => document,
+            // A file that doesn't exist yet is created on the first save.
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                let mut document = Document::new(&self.config);
+                document.set_file_type(self.file_types.detect(&file_name));
+                document.file_name = Some(file_name);
+                document
+            }
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!("Could not open {file_name}: {error}"));
+                return;
+            }
+        };
+        if let Some(warning) = lossy_warning(&document) {
+            self.status_message = StatusMessage::from(warning);
+        }
+        self.buffers.push(Buffer::from(document));
+        self.switch_buffer(self.buffers.len().saturating_sub(1));
+        self.check_swap();
+    }
+
+    // Offers to recover every open file's swap file, starting with the first.
+    fn check_swap_files(&mut self) {
+        if self.config.swap_interval == 0 {
+            return;
+        }
+        for index in 0..self.buffers.len() {
+            self.switch_buffer(index);
+            self.check_swap();
+        }
+        self.switch_buffer(0);
+    }
+
+    // Asks what to do with the swap file another session left behind for
+    // the current document, if there is one. The diff shows what recovering
+    // would change, and can be scrolled while the question is open. If that
+    // session is still running, its swap file is never removed.
+    fn check_swap(&mut self) {
+        if self.config.swap_interval == 0 {
+            return;
+        }
+        let Some((swap, running)) = self.document.stale_swap() else {
+            return;
+        };
+        let name = self.document.file_name.clone().unwrap_or_default();
+        let (found, discard) = match running {
+            Some(pid) => (format!("{name} is open in another hecto (pid {pid})"), "ignore"),
+            None => (format!("Unsaved changes to {name} found"), "discard"),
+        };
+        let saved_view = (self.cursor_position, self.offset);
+        // The document while the diff is shown in its place.
+        let mut hidden: Option<Document> = None;
+        let answer = loop {
+            let diff_choice = if hidden.is_some() { "hide diff" } else { "diff" };
+            let question = format!("{found}: (r)ecover, (d) {diff_choice}, (x) {discard}? ");
+            let answer = self
+                .prompt(&question, |editor, key, _| {
+                    if matches!(key, Key::Up | Key::Down | Key::PageUp | Key::PageDown) {
+                        editor.move_cursor(key);
+                        editor.scroll();
+                    }
+                })
+                .unwrap_or(None);
+            if answer.as_deref() != Some("d") {
+                break answer;
+            }
+            if let Some(document) = hidden.take() {
+                self.document = document;
+                (self.cursor_position, self.offset) = saved_view;
+            } else {
+                let diff = swap::diff(&self.document.text(), &swap);
+                let diff = Document::from_text(&diff, &self.config);
+                hidden = Some(mem::replace(&mut self.document, diff));
+                self.cursor_position = Position::default();
+                self.offset = Position::default();
+            }
+        };
+        if let Some(document) = hidden {
+            self.document = document;
+            (self.cursor_position, self.offset) = saved_view;
+        }
+        let message = match answer.as_deref() {
+            Some("r") => {
+                self.document.recover(&swap);
+                self.cursor_position = Position::default();
+                self.offset = Position::default();
+                "Recovered unsaved changes; save to keep them"
+            }
+            Some("x") if running.is_some() => "Left the other session's swap file alone",
+            Some("x") => {
+                self.document.remove_swap();
+                "Discarded unsaved changes"
+            }
+            // The swap file stays, so the question comes up again next time.
+            _ => "Kept the swap file",
+        };
+        self.status_message = StatusMessage::from(message.to_string());
+    }
+
+    // Writes the unsaved changes of every buffer to its swap file, at most
+    // once per `swap_interval`.
+    fn update_swap_files(&mut self) {
+        let interval = self.config.swap_interval;
+        if interval == 0 || self.last_swap.elapsed() < Duration::from_secs(interval) {
+            return;
+        }
+        self.last_swap = Instant::now();
+        let mut result = self.document.update_swap();
+        for buffer in &mut self.buffers {
+            result = result.and(buffer.document.update_swap());
+        }
+        if let Err(error) = result {
+            self.status_message = StatusMessage::from(format!("Could not write swap file: {error}"));
+        }
+    }
+
+    // Autosaves once `autosave_idle` seconds have passed without input, and
+    // not again before the next key press.
+    fn autosave_when_idle(&mut self) {
+        let last_autosave = self.last_autosave.as_ref().map(|(at, _)| *at);
+        if autosave_due(self.config.autosave_idle, Instant::now(), self.last_input, last_autosave) {
+            self.autosave();
+        }
+    }
+
+    // Saves every modified buffer that has a file name to save to.
+    fn autosave(&mut self) {
+        let mut saved = false;
+        let mut error = None;
+        for index in 0..self.buffers.len() {
+            let document = self.buffer_document_mut(index);
+            let Some(name) = document.file_name.clone().filter(|_| document.is_dirty()) else {
+                continue;
+            };
+            saved = true;
+            if let Err(reason) = document.save() {
+                error = Some(format!("{name}: {reason}"));
+            }
+        }
+        if saved {
+            self.last_autosave = Some((Instant::now(), error));
+        }
+    }
+
+    fn remove_swap_files(&mut self) {
+        self.document.remove_swap();
+        for buffer in &mut self.buffers {
+            buffer.document.remove_swap();
+        }
+    }
+
+    // Shows every buffer in the message bar, with `*` for unsaved changes
+    // and brackets around the current one.
+    fn list_buffers(&mut self) {
+        let entries: Vec<String> = (0..self.buffers.len())
+            .map(|index| {
+                let document = self.buffer_document(index);
+                let name = document.file_name.as_deref().unwrap_or("[No Name]");
+                let dirty = if document.is_dirty() { "*" } else { "" };
+                let entry = format!("{}:{name}{dirty}", index.saturating_add(1));
+                if index == self.current_buffer {
+                    format!("[{entry}]")
+                } else {
+                    entry
+                }
+            })
+            .collect();
+        self.status_message = StatusMessage::from(entries.join(" "));
+    }
+
+    fn close_buffer(&mut self) {
+        if self.document.is_dirty() {
+            let answer = self
+                .prompt("Buffer has unsaved changes. Close anyway? (y/n): ", |_, _, _| {})
+                .unwrap_or(None);
+            if answer.as_deref() != Some("y") {
+                self.status_message = StatusMessage::from("Close aborted".to_string());
+                return;
+            }
+        }
+        // Closing a buffer throws its changes away as deliberately as quitting.
+        self.document.remove_swap();
+        if self.buffers.len() == 1 {
+            // There is always something to edit.
+            self.document = Document::new(&self.config);
+            self.cursor_position = Position::default();
+            self.offset = Position::default();
+            self.mark = None;
+            return;
+        }
+        let closing = self.current_buffer;
+        let next = if closing.saturating_add(1) < self.buffers.len() {
+            closing.saturating_add(1)
+        } else {
+            closing.saturating_sub(1)
+        };
+        self.switch_buffer(next);
+        self.buffers.remove(closing);
+        if self.current_buffer > closing {
+            self.current_buffer = self.current_buffer.saturating_sub(1);
+        }
+        // Other panes showing the closed buffer show this one instead.
+        for view in &mut self.views {
+            if view.buffer == closing {
+                *view = View {
+                    buffer: self.current_buffer,
+                    ..View::default()
+                };
+            } else if view.buffer > closing {
+                view.buffer = view.buffer.saturating_sub(1);
+            }
+        }
+        self.list_buffers();
+    }
+
+    // The whole screen but the message bar.
+    fn screen(&self) -> Rect {
+        let size = self.terminal.size();
+        Rect {
+            x: 0,
+            y: 0,
+            width: size.width as usize,
+            // The terminal's height already leaves out a status line.
+            height: (size.height as usize).saturating_add(1),
+        }
+    }
+
+    fn pane_rect(&self, index: usize) -> Rect {
+        self.layout
+            .panes(self.screen())
+            .into_iter()
+            .find(|(pane, _)| *pane == index)
+            .map_or_else(|| self.screen(), |(_, rect)| rect)
+    }
+
+    // The text columns and lines of the focused pane, without the gutter
+    // and the status line.
+    fn view_size(&self) -> (usize, usize) {
+        let rect = self.pane_rect(self.current_view);
+        let gutter = self.gutter_width(&self.document);
+        (rect.width.saturating_sub(gutter), rect.height.saturating_sub(1))
+    }
+
+    // Room for the largest line number plus a space, so the gutter only
+    // grows when the document does.
+    fn gutter_width(&self, document: &Document) -> usize {
+        gutter_width(self.line_numbers, document.len())
+    }
+
+    // Switches the current document between LF and CRLF line endings.
+    fn convert_line_endings(&mut self) {
+        let line_ending = match self.document.line_ending() {
+            LineEnding::Lf => LineEnding::CrLf,
+            LineEnding::CrLf => LineEnding::Lf,
+        };
+        self.document.set_line_ending(line_ending);
+        self.status_message = StatusMessage::from(format!(
+            "Line endings: {} (takes effect on save)",
+            line_ending.name()
+        ));
+    }
+
+    fn toggle_wrap(&mut self) {
+        let (mode, name) = match self.wrap {
+            Wrap::Off => (Wrap::Anywhere, "anywhere"),
+            Wrap::Anywhere => (Wrap::Words, "at words"),
+            Wrap::Words => (Wrap::Off, "off"),
+        };
+        self.wrap = mode;
+        // `offset.x` means something else with wrapping on.
+        self.offset.x = 0;
+        for view in &mut self.views {
+            view.offset.x = 0;
+        }
+        self.status_message = StatusMessage::from(format!("Wrap: {name}"));
+        self.scroll();
+    }
+
+    // Where the screen lines of row `y` of the active document start.
+    fn line_starts(&self, y: usize) -> Vec<usize> {
+        let (width, _) = self.view_size();
+        wrap_row(&self.document, y, width, self.wrap, self.config.tab_width)
+    }
+
+    // Where the cursor is drawn, relative to the text area of its pane.
+    fn cursor_on_screen(&self) -> Position {
+        let Position { x, y } = self.cursor_position;
+        if self.wrap == Wrap::Off {
+            // saturation means we go beyond the allowed value for the type.
+            return Position {
+                x: self.column(y, x).saturating_sub(self.offset.x),
+                y: y.saturating_sub(self.offset.y),
+            };
+        }
+        let starts = self.line_starts(y);
+        let line = line_index(&starts, x);
+        let mut screen_y = line;
+        for row in self.offset.y..y {
+            screen_y = screen_y.saturating_add(self.line_starts(row).len());
+        }
+        Position {
+            x: self
+                .column(y, x)
+                .saturating_sub(self.column(y, starts[line]))
+                .saturating_add(indicator_width(line)),
+            y: screen_y.saturating_sub(self.offset.x),
+        }
+    }
+
+    // The screen column position `x` of row `y` starts at, counted from the
+    // start of the row.
+    fn column(&self, y: usize, x: usize) -> usize {
+        self.document
+            .row(y)
+            .map_or(0, |row| row.column(x, self.config.tab_width))
+    }
+
+    // The position in row `y` at screen column `column`.
+    fn index_at(&self, y: usize, column: usize) -> usize {
+        self.document
+            .row(y)
+            .map_or(0, |row| row.index_at(column, self.config.tab_width))
+    }
+
+    // The cursor moved `count` screen lines up or down, staying in the same
+    // column where the line is long enough.
+    fn move_screen_lines(&self, mut position: Position, down: bool, count: usize) -> Position {
+        for _ in 0..count {
+            let Position { x, y } = position;
+            let starts = self.line_starts(y);
+            let line = line_index(&starts, x);
+            let column = self.column(y, x).saturating_sub(self.column(y, starts[line]));
+            let (y, line) = if down {
+                if line.saturating_add(1) < starts.len() {
+                    (y, line.saturating_add(1))
+                } else if y < self.document.len() {
+                    (y.saturating_add(1), 0)
+                } else {
+                    break;
+                }
+            } else if line > 0 {
+                (y, line.saturating_sub(1))
+            } else if y > 0 {
+                let y = y.saturating_sub(1);
+                (y, self.line_starts(y).len().saturating_sub(1))
+            } else {
+                break;
+            };
+            let starts = self.line_starts(y);
+            // The last position on a line that doesn't end the row belongs to
+            // the next line already.
+            let end = starts
+                .get(line.saturating_add(1))
+                .map_or_else(|| self.row_len(y), |next| next.saturating_sub(1));
+            let x = self.index_at(y, self.column(y, starts[line]).saturating_add(column));
+            position = Position { x: cmp::min(x, end), y };
+        }
+        position
+    }
+
+    fn toggle_line_numbers(&mut self) {
+        let (mode, name) = match self.line_numbers {
+            LineNumbers::Off => (LineNumbers::Absolute, "absolute"),
+            LineNumbers::Absolute => (LineNumbers::Relative, "relative"),
+            LineNumbers::Relative => (LineNumbers::Hybrid, "hybrid"),
+            LineNumbers::Hybrid => (LineNumbers::Off, "off"),
+        };
+        self.line_numbers = mode;
+        self.status_message = StatusMessage::from(format!("Line numbers: {name}"));
+        // The text got narrower or wider.
+        self.scroll();
+    }
+
+    // The views in the order their panes appear on screen.
+    fn pane_order(&self) -> Vec<usize> {
+        self.layout
+            .panes(self.screen())
+            .into_iter()
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    fn view(&self, index: usize) -> View {
+        if index == self.current_view {
+            View {
+                buffer: self.current_buffer,
+                cursor_position: self.cursor_position,
+                offset: self.offset,
+            }
         } else {
-            self.status_message = StatusMessage::from("Error writing file".to_string());
+            self.views[index]
+        }
+    }
+
+    fn split_pane(&mut self, direction: Direction) {
+        let rect = self.pane_rect(self.current_view);
+        let too_small = match direction {
+            Direction::Horizontal => rect.height < 4,
+            Direction::Vertical => rect.width < 3,
+        };
+        if too_small {
+            self.status_message = StatusMessage::from("Not enough room to split".to_string());
+            return;
+        }
+        let new_view = self.views.len();
+        self.views.push(self.view(self.current_view));
+        self.layout.split(self.current_view, direction, new_view);
+        self.focus_view(new_view);
+    }
+
+    fn focus_view(&mut self, index: usize) {
+        if index == self.current_view || index >= self.views.len() {
+            return;
+        }
+        self.views[self.current_view] = self.view(self.current_view);
+        self.load_view(index);
+    }
+
+    fn load_view(&mut self, index: usize) {
+        let view = self.views[index];
+        self.current_view = index;
+        self.switch_buffer(view.buffer);
+        // Edits in another pane may have shortened the document meanwhile.
+        let y = cmp::min(view.cursor_position.y, self.document.len());
+        let x = cmp::min(view.cursor_position.x, self.row_len(y));
+        self.cursor_position = Position { x, y };
+        self.offset = view.offset;
+        self.scroll();
+    }
+
+    // Moves the focus along the panes from left to right and top to bottom.
+    fn cycle_pane(&mut self, forward: bool) {
+        let order = self.pane_order();
+        let count = order.len();
+        if count < 2 {
+            self.status_message = StatusMessage::from("No other panes".to_string());
+            return;
+        }
+        let position = order.iter().position(|index| *index == self.current_view).unwrap_or(0);
+        let next = if forward {
+            position.saturating_add(1) % count
+        } else {
+            position.checked_sub(1).unwrap_or(count.saturating_sub(1))
+        };
+        self.focus_view(order[next]);
+    }
+
+    fn resize_pane(&mut self, percent: isize) {
+        if self.layout.resize(self.current_view, percent) {
+            self.scroll();
+        } else {
+            self.status_message = StatusMessage::from("Only one pane".to_string());
+        }
+    }
+
+    fn close_pane(&mut self) {
+        let order = self.pane_order();
+        if order.len() < 2 {
+            self.status_message = StatusMessage::from("Only one pane".to_string());
+            return;
+        }
+        let closing = self.current_view;
+        let position = order.iter().position(|index| *index == closing).unwrap_or(0);
+        let next = if position > 0 { order[position.saturating_sub(1)] } else { order[1] };
+        self.layout.remove(closing);
+        self.views.remove(closing);
+        let next = if next > closing { next.saturating_sub(1) } else { next };
+        // The closed view's cursor is dropped rather than saved.
+        self.load_view(next);
+    }
+
+    // The document of buffer `index`, whether it's the active one or not.
+    fn buffer_document(&self, index: usize) -> &Document {
+        if index == self.current_buffer {
+            &self.document
+        } else {
+            &self.buffers[index].document
+        }
+    }
+
+    fn buffer_document_mut(&mut self, index: usize) -> &mut Document {
+        if index == self.current_buffer {
+            &mut self.document
+        } else {
+            &mut self.buffers[index].document
+        }
+    }
+
+    fn dirty_buffers(&self) -> usize {
+        (0..self.buffers.len())
+            .filter(|index| self.buffer_document(*index).is_dirty())
+            .count()
+    }
+
+    // Replaces the text just pasted with the kill before it.
+    fn yank_pop(&mut self) {
+        let Some((start, end)) = self
+            .last_yank
+            .filter(|_| matches!(self.previous_command, Some(Command::Paste | Command::YankPop)))
+        else {
+            self.status_message = StatusMessage::from("Previous command was not a yank".to_string());
+            return;
+        };
+        let Some(text) = self.clipboard.rotate() else {
+            return;
+        };
+        self.document.begin_undo_group();
+        self.document.delete_range(&start, &end);
+        let end = self.document.insert_str(&start, &text);
+        self.document.end_undo_group();
+        self.cursor_position = end;
+        self.last_yank = Some((start, end));
+    }
+
+    // Kills the rest of the line, or the line break if only blanks are left.
+    // Kills in a row are yanked back together.
+    fn kill_line(&mut self) {
+        let Some(row) = self.document.row(self.cursor_position.y) else {
+            return;
+        };
+        let end = kill_line_end(row, self.cursor_position);
+        let text = self.document.delete_range(&self.cursor_position, &end);
+        if text.is_empty() {
+            return;
+        }
+        let result = if self.previous_command == Some(Command::KillLine) {
+            self.clipboard.append(&text)
+        } else {
+            self.clipboard.copy(text)
+        };
+        if let Err(error) = result {
+            self.status_message = StatusMessage::from(format!("Killed, but clipboard failed: {error}"));
         }
     }
 
     fn process_keypress(&mut self) -> Result<(), std::io::Error> {
-        let pressed_key = Terminal::read_key()?;
-        match pressed_key {
-            Key::Ctrl('q') => {
-                if self.quit_times > 0 && self.document.is_dirty() {
-                    self.status_message = StatusMessage::from(format!("WARNING!!! File has unsaved changes. Press Ctrl-Q {} more times to quit.", self.quit_times));
-                    self.quit_times -= 1;
-                    return Ok(());
+        let pressed_key = match self.terminal.read_event_timeout(INPUT_TIMEOUT)? {
+            // Nothing happened, but the screen may still need an update.
+            None | Some(Event::FocusGained) => return Ok(()),
+            Some(Event::FocusLost) => {
+                if self.config.autosave_on_focus_loss {
+                    self.autosave();
                 }
-                self.should_quit = true;
+                return Ok(());
             }
-            Key::Ctrl('s') => self.save(),
-            Key::Char(c) => {
-                self.document.insert(&self.cursor_position, c);
-                self.move_cursor(Key::Right);
+            Some(Event::Key(key)) => {
+                self.last_input = Instant::now();
+                key
             }
-            Key::Delete => self.document.delete(&self.cursor_position),
-            Key::Backspace => {
-                if self.cursor_position.x > 0 || self.cursor_position.y > 0 {
-                    self.move_cursor(Key::Left);
-                    self.document.delete(&self.cursor_position);
+            Some(Event::Paste(text)) => {
+                self.last_input = Instant::now();
+                self.paste_text(&text);
+                self.previous_command = None;
+                self.scroll();
+                return Ok(());
+            }
+        };
+        if let Some(action) = self.modal_action(pressed_key) {
+            self.previous_command = None;
+            self.vim_action(action);
+        } else {
+            self.pending_keys.push(pressed_key);
+            match self.keymap.lookup(&self.pending_keys) {
+                Lookup::Command(command) => {
+                    self.pending_keys.clear();
+                    if command == Command::Quit {
+                        let dirty = self.dirty_buffers();
+                        if self.quit_times > 0 && dirty > 0 {
+                            let keys = self.keymap.describe(Command::Quit).unwrap_or_default();
+                            let what = if dirty == 1 {
+                                "File has".to_string()
+                            } else {
+                                format!("{dirty} files have")
+                            };
+                            self.status_message = StatusMessage::from(format!("WARNING!!! {what} unsaved changes. Press {keys} {} more times to quit.", self.quit_times));
+                            self.quit_times -= 1;
+                            return Ok(());
+                        }
+                        self.should_quit = true;
+                    }
+                    self.execute(command);
+                    self.previous_command = Some(command);
+                }
+                Lookup::Prefix => {
+                    self.status_message = StatusMessage::from(format!("{}-", keymap::keys_name(&self.pending_keys)));
+                    return Ok(());
+                }
+                Lookup::Unbound => {
+                    self.previous_command = None;
+                    let keys = mem::take(&mut self.pending_keys);
+                    match keys.as_slice() {
+                        [Key::Char(c)] => self.insert_char(*c),
+                        // Esc gives up on a half typed binding.
+                        [.., Key::Esc] => self.status_message = StatusMessage::from(String::new()),
+                        _ => {
+                            self.status_message = StatusMessage::from(format!("{} is not bound", keymap::keys_name(&keys)));
+                        }
+                    }
                 }
             }
-            Key::Up
-            | Key::Down
-            | Key::Left
-            | Key::Right
-            | Key::PageUp
-            | Key::PageDown
-            | Key::End
-            | Key::Home => self.move_cursor(pressed_key),
-            _ => (),
+        }
+        if self.vim.as_ref().is_some_and(|vim| vim.mode() != Mode::Insert) {
+            self.clamp_to_last_char();
         }
         self.scroll();
-        if self.quit_times < QUIT_TIMES {
-            self.quit_times = QUIT_TIMES;
+        if self.quit_times < self.config.quit_times {
+            self.quit_times = self.config.quit_times;
             self.status_message = StatusMessage::from(String::new());
         }
         Ok(())
     }
 
+    fn execute(&mut self, command: Command) {
+        match command {
+            Command::Quit => (),
+            Command::Save => self.save(),
+            Command::Find => self.search(false),
+            Command::FindRegex => self.search(true),
+            Command::Replace => self.replace(),
+            Command::Undo => self.undo(),
+            Command::Redo => self.redo(),
+            Command::HistoryBack => self.travel(false),
+            Command::HistoryForward => self.travel(true),
+            Command::ToggleMark => self.toggle_mark(),
+            Command::ClearMark => self.mark = None,
+            Command::Copy => self.copy(),
+            Command::Cut => self.cut(),
+            Command::Paste => self.paste(),
+            Command::OpenFile => self.open_file(),
+            Command::NextBuffer => self.cycle_buffer(true),
+            Command::PreviousBuffer => self.cycle_buffer(false),
+            Command::ListBuffers => self.list_buffers(),
+            Command::CloseBuffer => self.close_buffer(),
+            Command::SplitHorizontal => self.split_pane(Direction::Horizontal),
+            Command::SplitVertical => self.split_pane(Direction::Vertical),
+            Command::NextPane => self.cycle_pane(true),
+            Command::PreviousPane => self.cycle_pane(false),
+            Command::GrowPane => self.resize_pane(PANE_RESIZE_STEP),
+            Command::ShrinkPane => self.resize_pane(PANE_RESIZE_STEP.saturating_neg()),
+            Command::ClosePane => self.close_pane(),
+            Command::ToggleLineNumbers => self.toggle_line_numbers(),
+            Command::ToggleWrap => self.toggle_wrap(),
+            Command::ConvertLineEndings => self.convert_line_endings(),
+            Command::SaveAsEncoding => self.save_as_encoding(),
+            Command::KillLine => self.kill_line(),
+            Command::YankPop => self.yank_pop(),
+            Command::Delete => {
+                if !self.delete_selection() {
+                    self.document.delete(&self.cursor_position);
+                }
+            }
+            Command::Backspace => {
+                if !self.delete_selection()
+                    && (self.cursor_position.x > 0 || self.cursor_position.y > 0)
+                {
+                    self.move_cursor(Key::Left);
+                    self.document.delete(&self.cursor_position);
+                }
+            }
+            Command::SelectUp | Command::SelectDown | Command::SelectLeft | Command::SelectRight => {
+                if self.mark.is_none() {
+                    self.mark = Some(self.cursor_position);
+                    self.shift_selection = true;
+                }
+                self.move_cursor(match command {
+                    Command::SelectUp => Key::Up,
+                    Command::SelectDown => Key::Down,
+                    Command::SelectLeft => Key::Left,
+                    _ => Key::Right,
+                });
+            }
+            Command::MoveUp
+            | Command::MoveDown
+            | Command::MoveLeft
+            | Command::MoveRight
+            | Command::PageUp
+            | Command::PageDown
+            | Command::LineStart
+            | Command::LineEnd => {
+                if self.shift_selection {
+                    self.mark = None;
+                    self.shift_selection = false;
+                }
+                self.move_cursor(match command {
+                    Command::MoveUp => Key::Up,
+                    Command::MoveDown => Key::Down,
+                    Command::MoveLeft => Key::Left,
+                    Command::MoveRight => Key::Right,
+                    Command::PageUp => Key::PageUp,
+                    Command::PageDown => Key::PageDown,
+                    Command::LineStart => Key::Home,
+                    _ => Key::End,
+                });
+            }
+        }
+    }
+
+    // The modal layer gets the first look at every key, except in the
+    // middle of a multi-key binding.
+    fn modal_action(&mut self, key: Key) -> Option<Action> {
+        if !self.pending_keys.is_empty() {
+            return None;
+        }
+        match self.vim.as_mut()?.handle(key) {
+            Action::Pass => None,
+            action => Some(action),
+        }
+    }
+
+    fn vim_action(&mut self, action: Action) {
+        let Position { x, y } = self.cursor_position;
+        match action {
+            Action::Move(motion, count) => {
+                self.cursor_position = vim::target(&self.document, self.cursor_position, motion, count);
+            }
+            Action::Operate(operator, motion, count) => {
+                let (start, end, linewise) =
+                    vim::operator_range(&self.document, self.cursor_position, operator, motion, count);
+                if linewise {
+                    self.operate_lines(operator, start.y, end.y);
+                } else {
+                    self.operate(operator, start, end);
+                }
+            }
+            Action::OperateLines(operator, count) => {
+                let last = cmp::min(
+                    y.saturating_add(count).saturating_sub(1),
+                    self.document.len().saturating_sub(1),
+                );
+                self.operate_lines(operator, y, last);
+            }
+            Action::OperateSelection(operator) => {
+                if let Some((start, end)) = self.visual_selection() {
+                    self.operate(operator, start, end);
+                }
+                self.mark = None;
+            }
+            Action::Insert(at) => {
+                let len = self.row_len(y);
+                match at {
+                    InsertAt::Cursor => (),
+                    InsertAt::After => self.cursor_position.x = cmp::min(x.saturating_add(1), len),
+                    InsertAt::LineStart => self.cursor_position.x = vim::first_non_blank(&self.document, y),
+                    InsertAt::LineEnd => self.cursor_position.x = len,
+                    InsertAt::LineBelow => {
+                        self.document.insert(&Position { x: len, y }, '\n');
+                        self.cursor_position = Position {
+                            x: 0,
+                            y: y.saturating_add(1),
+                        };
+                    }
+                    InsertAt::LineAbove => {
+                        self.document.insert(&Position { x: 0, y }, '\n');
+                        self.cursor_position = Position { x: 0, y };
+                    }
+                }
+            }
+            Action::Paste { before } => self.vim_paste(before),
+            Action::Undo => self.undo(),
+            Action::Redo => self.redo(),
+            Action::StartVisual => {
+                self.mark = Some(self.cursor_position);
+                self.shift_selection = false;
+            }
+            Action::ExitInsert => {
+                // Like Vim, leave the cursor on the last inserted character.
+                self.cursor_position.x = x.saturating_sub(1);
+                self.mark = None;
+            }
+            Action::Cancel => self.mark = None,
+            Action::Pending | Action::Pass => (),
+        }
+    }
+
+    // Deletes, changes or yanks the text from `start` up to `end`.
+    fn operate(&mut self, operator: Operator, start: Position, end: Position) {
+        let text = if operator == Operator::Yank {
+            self.document.text_range(&start, &end)
+        } else {
+            self.document.delete_range(&start, &end)
+        };
+        self.cursor_position = start;
+        self.yank(text, false);
+    }
+
+    // Deletes, changes or yanks the whole lines `first` to `last`.
+    fn operate_lines(&mut self, operator: Operator, first: usize, last: usize) {
+        let line_start = |y| Position { x: 0, y };
+        let last_end = Position {
+            x: self.row_len(last),
+            y: last,
+        };
+        let mut text = self.document.text_range(&line_start(first), &last_end);
+        text.push('\n');
+        match operator {
+            Operator::Yank => self.cursor_position.y = first,
+            Operator::Change => {
+                self.document.delete_range(&line_start(first), &last_end);
+                self.cursor_position = line_start(first);
+            }
+            Operator::Delete => {
+                if last.saturating_add(1) < self.document.len() {
+                    self.document.delete_range(&line_start(first), &line_start(last.saturating_add(1)));
+                } else if first > 0 {
+                    // The last line has no line break after it, so take the
+                    // one before the first line instead.
+                    let before = first.saturating_sub(1);
+                    let previous_end = Position {
+                        x: self.row_len(before),
+                        y: before,
+                    };
+                    self.document.delete_range(&previous_end, &last_end);
+                } else {
+                    self.document.delete_range(&line_start(first), &last_end);
+                }
+                let y = cmp::min(first, self.document.len().saturating_sub(1));
+                self.cursor_position = Position {
+                    x: vim::first_non_blank(&self.document, y),
+                    y,
+                };
+            }
+        }
+        self.yank(text, true);
+    }
+
+    fn yank(&mut self, text: String, linewise: bool) {
+        if text.is_empty() {
+            return;
+        }
+        if let Some(vim) = &mut self.vim {
+            vim.linewise = linewise;
+        }
+        if let Err(error) = self.clipboard.copy(text) {
+            self.status_message = StatusMessage::from(format!("Clipboard failed: {error}"));
+        }
+    }
+
+    // `p` and `P`: lines go below or above the current one, other text after
+    // or before the cursor.
+    fn vim_paste(&mut self, before: bool) {
+        let text = match self.clipboard.paste() {
+            Ok(text) => text.replace("\r\n", "\n"),
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!("Paste failed: {error}"));
+                return;
+            }
+        };
+        if text.is_empty() {
+            return;
+        }
+        let Position { x, y } = self.cursor_position;
+        let linewise = self.vim.as_ref().is_some_and(|vim| vim.linewise) && text.ends_with('\n');
+        if linewise {
+            let below = y.saturating_add(1);
+            if before || below < self.document.len() {
+                let y = if before { y } else { below };
+                self.document.insert_str(&Position { x: 0, y }, &text);
+                self.cursor_position = Position { x: 0, y };
+            } else {
+                // There is no line below to insert in front of.
+                let text = format!("\n{}", text.trim_end_matches('\n'));
+                self.document.insert_str(&Position { x: self.row_len(y), y }, &text);
+                self.cursor_position = Position { x: 0, y: below };
+            }
+        } else {
+            let at = if before {
+                self.cursor_position
+            } else {
+                Position {
+                    x: cmp::min(x.saturating_add(1), self.row_len(y)),
+                    y,
+                }
+            };
+            let end = self.document.insert_str(&at, &text);
+            self.cursor_position = Position {
+                x: end.x.saturating_sub(1),
+                y: end.y,
+            };
+        }
+    }
+
+    // Outside of insert mode the cursor sits on a character, not after the
+    // last one.
+    fn clamp_to_last_char(&mut self) {
+        let last_line = self.document.len().saturating_sub(1);
+        if self.cursor_position.y > last_line {
+            self.cursor_position.y = last_line;
+        }
+        let len = self.row_len(self.cursor_position.y);
+        if len > 0 && self.cursor_position.x >= len {
+            self.cursor_position.x = len.saturating_sub(1);
+        }
+    }
+
+    fn row_len(&self, y: usize) -> usize {
+        self.document.row(y).map_or(0, |row| row.len())
+    }
+
+    fn insert_char(&mut self, c: char) {
+        self.delete_selection();
+        self.document.insert(&self.cursor_position, c);
+        self.move_cursor(Key::Right);
+    }
+
     fn scroll(&mut self) {
+        if self.wrap != Wrap::Off {
+            self.scroll_wrapped();
+            return;
+        }
         let Position { x, y } = self.cursor_position;
-        let width = self.terminal.size().width as usize;
-        let height = self.terminal.size().height as usize;
+        let (width, height) = self.view_size();
+        // The columns under the cursor, two for a wide character.
+        let start = self.column(y, x);
+        let end = if x < self.row_len(y) {
+            self.column(y, x.saturating_add(1))
+        } else {
+            start.saturating_add(1)
+        };
         let mut offset = &mut self.offset;
         if y < offset.y {
             offset.y = y;
         } else if y >= offset.y.saturating_add(height) {
             offset.y = y.saturating_sub(height).saturating_add(1);
         }
-        if x < offset.x {
-            offset.x = x;
-        } else if x >= offset.x.saturating_add(width) {
-            offset.x = x.saturating_sub(width).saturating_add(1);
+        if start < offset.x {
+            offset.x = start;
+        } else if end > offset.x.saturating_add(width) {
+            offset.x = end.saturating_sub(width);
+        }
+    }
+
+    // Like scroll, but counting screen lines rather than rows.
+    fn scroll_wrapped(&mut self) {
+        let (_, height) = self.view_size();
+        let Position { x, y } = self.cursor_position;
+        let line = line_index(&self.line_starts(y), x);
+        // Edits or a narrower pane may have left fewer lines in the top row.
+        let top_lines = self.line_starts(self.offset.y).len();
+        self.offset.x = cmp::min(self.offset.x, top_lines.saturating_sub(1));
+        if (y, line) < (self.offset.y, self.offset.x) {
+            self.offset = Position { x: line, y };
+            return;
+        }
+        // The lowest the top can be with the cursor still on the screen.
+        let mut top = (y, line);
+        for _ in 1..height {
+            if top.1 > 0 {
+                top.1 = top.1.saturating_sub(1);
+            } else if top.0 > 0 {
+                top.0 = top.0.saturating_sub(1);
+                top.1 = self.line_starts(top.0).len().saturating_sub(1);
+            } else {
+                break;
+            }
+        }
+        if top > (self.offset.y, self.offset.x) {
+            self.offset = Position { x: top.1, y: top.0 };
         }
     }
 
     fn move_cursor(&mut self, key: Key) {
-        let terminal_height = self.terminal.size().height as usize;
+        let (_, terminal_height) = self.view_size();
+        if self.wrap != Wrap::Off {
+            let screen_lines = match key {
+                Key::Up => Some((false, 1)),
+                Key::Down => Some((true, 1)),
+                Key::PageUp => Some((false, terminal_height)),
+                Key::PageDown => Some((true, terminal_height)),
+                _ => None,
+            };
+            if let Some((down, count)) = screen_lines {
+                self.cursor_position = self.move_screen_lines(self.cursor_position, down, count);
+                return;
+            }
+        }
         let Position { mut y, mut x } = self.cursor_position;
         let height = self.document.len();
         let mut width = if let Some(row) = self.document.row(y) {
@@ -249,6 +1657,12 @@ impl Editor {
             Key::End => x = width,
             _ => (),
         }
+        // Moving up or down keeps the screen column rather than the number
+        // of characters, which differ with tabs and wide characters.
+        if matches!(key, Key::Up | Key::Down | Key::PageUp | Key::PageDown) {
+            let column = self.column(self.cursor_position.y, self.cursor_position.x);
+            x = self.index_at(y, column);
+        }
         width = if let Some(row) = self.document.row(y) {
             row.len()
         } else {
@@ -260,9 +1674,8 @@ impl Editor {
         self.cursor_position = Position { x, y };
     }
 
-    fn draw_welcome_message(&self) {
+    fn draw_welcome_message(&self, width: usize) {
         let mut welcome_message = format!("Hecto editor -- version {}", VERSION);
-        let width = self.terminal.size().width as usize;
         let len = welcome_message.len();
         let padding = width.saturating_sub(len) / 2;
 
@@ -271,41 +1684,124 @@ impl Editor {
 
         welcome_message = format!("~{spaces}{welcome_message}");
         welcome_message.truncate(width);
-        println!("{welcome_message}\r");
+        print!("{welcome_message}");
     }
-    pub fn draw_row(&self, row: &Row) {
-        let width = self.terminal.size().width as usize;
-        let start = self.offset.x;
-        let end = self.offset.x.saturating_add(width);
-        let row = row.render(start, end);
-        println!("{row}\r");
+    pub fn draw_row(
+        &self,
+        row: &Row,
+        offset: usize,
+        width: usize,
+        selection: Option<Range<usize>>,
+    ) {
+        let end = offset.saturating_add(width);
+        let row = row.render(offset, end, selection, &self.theme, &self.config);
+        print!("{row}");
     }
 
-    fn draw_rows(&self) {
-        let height = self.terminal.size().height;
+    fn draw_pane(&self, index: usize, rect: Rect) {
+        let view = self.view(index);
+        let focused = index == self.current_view;
+        let document = self.buffer_document(view.buffer);
+        let selection = if focused { self.selection() } else { None };
+        let height = rect.height.saturating_sub(1);
+        let gutter = cmp::min(self.gutter_width(document), rect.width);
+        let width = rect.width.saturating_sub(gutter);
+        let wrapping = self.wrap != Wrap::Off;
+        let mut y = view.offset.y;
+        // The screen line of row `y` to draw next, when wrapping.
+        let mut line = if wrapping { view.offset.x } else { 0 };
         for terminal_row in 0..height {
-            Terminal::clear_current_line();
-            if let Some(row) = self.document.row(self.offset.y.saturating_add(terminal_row as usize)) {
-                self.draw_row(row);
-            } else if self.document.is_empty() && terminal_row == height / 3 {
-                self.draw_welcome_message();
+            start_pane_line(rect, terminal_row);
+            if let Some(row) = document.row(y) {
+                // The columns of the row that go on this screen line.
+                let (start, end, last_line) = if wrapping {
+                    let starts = wrap_row(document, y, width, self.wrap, self.config.tab_width);
+                    line = cmp::min(line, starts.len().saturating_sub(1));
+                    let next = starts.get(line.saturating_add(1)).copied();
+                    let tab_width = self.config.tab_width;
+                    (
+                        row.column(starts[line], tab_width),
+                        row.column(next.unwrap_or_else(|| row.len()), tab_width),
+                        next.is_none(),
+                    )
+                } else {
+                    (view.offset.x, view.offset.x.saturating_add(width), true)
+                };
+                if gutter > 0 {
+                    // Only the first screen line of a row gets its number.
+                    let number = if line == 0 {
+                        line_number(self.line_numbers, y, view.cursor_position.y).to_string()
+                    } else {
+                        String::new()
+                    };
+                    let number = format!("{number:>digits$} ", digits = gutter.saturating_sub(1));
+                    print!(
+                        "{}{}{}",
+                        self.theme.line_number_color(),
+                        &number[..gutter],
+                        self.theme.reset_fg()
+                    );
+                }
+                if line > 0 {
+                    print!(
+                        "{}{WRAP_INDICATOR}{}",
+                        self.theme.line_number_color(),
+                        self.theme.reset_fg()
+                    );
+                }
+                let selected = selection.and_then(|(start, end)| selected_in_row(start, end, y, row.len()));
+                self.draw_row(row, start, end.saturating_sub(start), selected);
+                if last_line {
+                    y = y.saturating_add(1);
+                    line = 0;
+                } else {
+                    line = line.saturating_add(1);
+                }
             } else {
-                println!("~\r");
+                if document.is_empty() && terminal_row == height / 3 {
+                    self.draw_welcome_message(rect.width);
+                } else {
+                    print!("~");
+                }
+                y = y.saturating_add(1);
             }
         }
+        start_pane_line(rect, height);
+        self.draw_status_bar(&view, rect.width, focused);
+    }
+
+    // Asks for a line of input; an empty answer counts as cancelled.
+    fn prompt<C>(&mut self, prompt: &str, callback: C) -> Result<Option<String>, std::io::Error>
+    where
+        C: FnMut(&mut Self, Key, &String),
+    {
+        Ok(self
+            .prompt_allow_empty(prompt, callback)?
+            .filter(|answer| !answer.is_empty()))
     }
 
-    fn prompt(&mut self, prompt: &str) -> Result<Option<String>, std::io::Error> {
+    // Like `prompt`, but Enter on an empty line answers `Some("")`; only ESC
+    // cancels.
+    fn prompt_allow_empty<C>(&mut self, prompt: &str, mut callback: C) -> Result<Option<String>, std::io::Error>
+    where
+        C: FnMut(&mut Self, Key, &String),
+    {
         let mut result = String::new();
         loop {
             self.status_message = StatusMessage::from(format!("{}{}", prompt, result));
             self.refresh_screen()?;
 
-            match Terminal::read_key()? {
+            let key = match self.terminal.read_event()? {
+                Event::Key(key) => key,
+                Event::Paste(text) => {
+                    result.extend(text.chars().filter(|c| !c.is_control()));
+                    continue;
+                }
+                Event::FocusGained | Event::FocusLost => continue,
+            };
+            match key {
                 Key::Backspace => {
-                    if !result.is_empty() {
-                        result.truncate(result.len() - 1);
-                    }
+                    result.pop();
                 }
                 Key::Char('\n') => break,
                 Key::Char(c) => {
@@ -314,23 +1810,21 @@ impl Editor {
                     }
                 }
                 Key::Esc => {
-                    result.truncate(0);
-                    break;
+                    self.status_message = StatusMessage::from(String::new());
+                    return Ok(None);
                 }
                 _ => (),
             }
-            self.status_message = StatusMessage::from(String::new());
-            if result.is_empty() {
-                return Ok(None);
-            }
+            callback(self, key, &result);
         }
+        self.status_message = StatusMessage::from(String::new());
         Ok(Some(result))
     }
 
-    fn draw_status_bar(&self) {
+    fn draw_status_bar(&self, view: &View, width: usize, focused: bool) {
         let mut status;
-        let width = self.terminal.size().width as usize;
-        let modified_indicator = if self.document.is_dirty() {
+        let document = self.buffer_document(view.buffer);
+        let modified_indicator = if document.is_dirty() {
             " (modified)"
         } else {
             ""
@@ -338,52 +1832,326 @@ impl Editor {
 
 
         let mut file_name = "[No Name]".to_string();
-        if let Some(name) = &self.document.file_name {
-            file_name = name.clone();
-            file_name.truncate(20);
+        if let Some(name) = &document.file_name {
+            file_name = truncate_to_width(name, 20).to_string();
         }
         status = format!(
             "{} - {} lines{}",
             file_name,
-            self.document.len(),
+            document.len(),
             modified_indicator
         );
+        if self.buffers.len() > 1 {
+            status = format!("[{}/{}] {status}", view.buffer.saturating_add(1), self.buffers.len());
+        }
+        if let Some(vim) = self.vim.as_ref().filter(|_| focused) {
+            status = format!("{} | {status}", vim.mode().name());
+        }
+        if let Some((at, error)) = self.last_autosave.as_ref().filter(|_| focused) {
+            match error {
+                Some(error) => status.push_str(&format!(" | autosave failed: {error}")),
+                None => status.push_str(&format!(" | autosaved {} ago", elapsed_name(at.elapsed()))),
+            }
+        }
 
 
+        let bom = if document.has_bom() && document.encoding() == Encoding::Utf8 {
+            " BOM"
+        } else {
+            ""
+        };
         let line_indicator = format!(
-            "{}/{}",
-            self.cursor_position.y.saturating_add(1),
-            self.document.len()
+            "{}{bom} | {} | {} | {}/{}",
+            document.encoding().name(),
+            document.line_ending().name(),
+            document.file_type(),
+            view.cursor_position.y.saturating_add(1),
+            document.len()
         );
 
-        #[allow(clippy::integer_arithmetic)]
-        let len = status.len() + line_indicator.len();
+        let len = text_width(&status).saturating_add(text_width(&line_indicator));
 
         if width > len {
             status.push_str(&" ".repeat(width.saturating_sub(len)));
         }
         status = format!("{status}{line_indicator}");
 
-        status.truncate(width);
-        Terminal::set_bg_color(STATUS_BG_COLOR);
-        Terminal::set_fg_color(STATUS_FG_COLOR);
-        println!("{status}\r");
-        Terminal::reset_fg_color();
-        Terminal::reset_bg_color();
+        let status = truncate_to_width(&status, width);
+        if self.theme.has_colors() && focused {
+            Terminal::set_colors(&self.theme.status_colors());
+            print!("{status}");
+            Terminal::reset_fg_color();
+            Terminal::reset_bg_color();
+        } else {
+            // Without colors, reverse video still sets the bar apart. Panes
+            // without focus always use it, so the focused one stands out.
+            print!("{}{status}{}", style::Invert, style::NoInvert);
+        }
     }
 
     fn draw_message_bar(&self) {
+        Terminal::cursor_position(&Position {
+            x: 0,
+            y: (self.terminal.size().height as usize).saturating_add(1),
+        });
         Terminal::clear_current_line();
         let message = &self.status_message;
-        if Instant::now() - message.time < Duration::new(5, 0) {
-            let mut text = message.text.clone();
-            text.truncate(self.terminal.size().width as usize);
-            println!("{}\r", text);
+        if Instant::now() - message.time < Duration::new(self.config.message_timeout, 0) {
+            let text = truncate_to_width(&message.text, self.terminal.size().width as usize);
+            Terminal::set_colors(&self.theme.message_color());
+            print!("{}", text);
+            Terminal::reset_fg_color();
         }
     }
 }
 
+// The width of the gutter for a document of `lines` lines, including the
+// space after the numbers.
+fn gutter_width(line_numbers: LineNumbers, lines: usize) -> usize {
+    if line_numbers == LineNumbers::Off {
+        return 0;
+    }
+    let digits = cmp::max(lines, 1).to_string().len();
+    digits.saturating_add(1)
+}
+
+// The number shown in the gutter for line `y` when the cursor is on line
+// `cursor_y`.
+fn line_number(line_numbers: LineNumbers, y: usize, cursor_y: usize) -> usize {
+    let distance = y.abs_diff(cursor_y);
+    match line_numbers {
+        LineNumbers::Relative => distance,
+        LineNumbers::Hybrid if distance > 0 => distance,
+        _ => y.saturating_add(1),
+    }
+}
+
+// Where `kill_line` from `at` stops: the end of the row, or the start of
+// the next one if only blanks are left.
+fn kill_line_end(row: &Row, at: Position) -> Position {
+    if row.substring(at.x, row.len()).trim().is_empty() {
+        Position {
+            x: 0,
+            y: at.y.saturating_add(1),
+        }
+    } else {
+        Position { x: row.len(), y: at.y }
+    }
+}
+
+// `a` and `b` with the one nearer the start of the document first.
+fn ordered(a: Position, b: Position) -> (Position, Position) {
+    if (a.y, a.x) < (b.y, b.x) {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+// The part of row `y`, `len` graphemes long, that lies inside the selection
+// from `start` to `end`.
+fn selected_in_row(start: Position, end: Position, y: usize, len: usize) -> Option<Range<usize>> {
+    if y < start.y || y > end.y {
+        return None;
+    }
+    let from = if y == start.y { start.x } else { 0 };
+    let to = if y == end.y { end.x } else { len };
+    Some(from..to)
+}
+
+// Whether `now` is `idle` seconds or more after the last input, with no
+// autosave since then. Zero turns idle autosave off.
+fn autosave_due(idle: u64, now: Instant, last_input: Instant, last_autosave: Option<Instant>) -> bool {
+    idle > 0
+        && now.saturating_duration_since(last_input) >= Duration::from_secs(idle)
+        && last_autosave.is_none_or(|at| at < last_input)
+}
+
+// `elapsed` rounded down to seconds, minutes or hours, e.g. `5m`.
+fn elapsed_name(elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs();
+    if seconds < 60 {
+        format!("{seconds}s")
+    } else if seconds < 3600 {
+        format!("{}m", seconds / 60)
+    } else {
+        format!("{}h", seconds / 3600)
+    }
+}
+
+// Where the screen lines of row `y` start, for text `width` columns wide.
+// Continuation lines lose a column to the wrap indicator.
+fn wrap_row(document: &Document, y: usize, width: usize, wrap: Wrap, tab_width: usize) -> Vec<usize> {
+    match (wrap, document.row(y)) {
+        (Wrap::Off, _) | (_, None) => vec![0],
+        (_, Some(row)) => row.wrap(
+            width,
+            width.saturating_sub(indicator_width(1)),
+            wrap == Wrap::Words,
+            tab_width,
+        ),
+    }
+}
+
+// The screen line of a wrapped row, given where its lines start, that
+// position `x` is on.
+fn line_index(starts: &[usize], x: usize) -> usize {
+    starts.iter().rposition(|start| *start <= x).unwrap_or(0)
+}
+
+// Columns taken up by the wrap indicator on screen line `line` of a row.
+fn indicator_width(line: usize) -> usize {
+    if line > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+// Moves to line `line` of the pane and clears it, after drawing the
+// separator from the pane on the left if there is one.
+fn start_pane_line(rect: Rect, line: usize) {
+    let y = rect.y.saturating_add(line);
+    if rect.x > 0 {
+        Terminal::cursor_position(&Position {
+            x: rect.x.saturating_sub(1),
+            y,
+        });
+        print!("│");
+    } else {
+        Terminal::cursor_position(&Position { x: 0, y });
+    }
+    Terminal::clear_until_newline();
+}
+
+// The startup message, showing whatever keys the main commands are bound to.
+fn help(keymap: &Keymap) -> String {
+    let commands = [
+        (Command::Find, "find"),
+        (Command::FindRegex, "regex find"),
+        (Command::Replace, "replace"),
+        (Command::Undo, "undo"),
+        (Command::Redo, "redo"),
+        (Command::Cut, "cut"),
+        (Command::Copy, "copy"),
+        (Command::Paste, "paste"),
+        (Command::Save, "save"),
+        (Command::Quit, "quit"),
+    ];
+    let entries: Vec<String> = commands
+        .iter()
+        .filter_map(|(command, label)| keymap.describe(*command).map(|keys| format!("{keys} = {label}")))
+        .collect();
+    format!("HELP: {}", entries.join(" | "))
+}
+
+// Splits the command line into the `--config` path and the files to open.
+fn parse_args(mut args: impl Iterator<Item = String>) -> (Option<String>, Vec<String>) {
+    let mut config_path = None;
+    let mut file_names = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            config_path = args.next();
+        } else if let Some(path) = arg.strip_prefix("--config=") {
+            config_path = Some(path.to_string());
+        } else {
+            file_names.push(arg);
+        }
+    }
+    (config_path, file_names)
+}
+
 fn die(e: std::io::Error) {
     Terminal::clear_screen();
     panic!("Error: {e:?}\r");
-}
\ No newline at end of file
+}
+
+// Tells that `document` had bytes it couldn't decode, which now show as
+// U+FFFD.
+fn lossy_warning(document: &Document) -> Option<String> {
+    document.is_lossy().then(|| {
+        format!(
+            "{} isn't valid {}; bad bytes show as U+FFFD",
+            document.file_name.as_deref().unwrap_or_default(),
+            document.encoding().name()
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(x: usize, y: usize) -> Position {
+        Position { x, y }
+    }
+
+    #[test]
+    fn autosave_waits_for_the_idle_time_once() {
+        let input = Instant::now();
+        let later = |seconds| input + Duration::from_secs(seconds);
+        assert!(!autosave_due(0, later(100), input, None));
+        assert!(!autosave_due(5, later(4), input, None));
+        assert!(autosave_due(5, later(5), input, None));
+        // Not again until there has been more input.
+        assert!(!autosave_due(5, later(20), input, Some(later(5))));
+        assert!(autosave_due(5, later(20), input, Some(input - Duration::from_secs(1))));
+        assert!(autosave_due(5, later(20), later(10), Some(later(5))));
+    }
+
+    #[test]
+    fn elapsed_time_is_rounded_down() {
+        assert_eq!(elapsed_name(Duration::from_millis(59_999)), "59s");
+        assert_eq!(elapsed_name(Duration::from_secs(60)), "1m");
+        assert_eq!(elapsed_name(Duration::from_secs(3599)), "59m");
+        assert_eq!(elapsed_name(Duration::from_secs(7300)), "2h");
+    }
+
+    #[test]
+    fn gutter_fits_the_last_line_number() {
+        assert_eq!(gutter_width(LineNumbers::Off, 500), 0);
+        assert_eq!(gutter_width(LineNumbers::Absolute, 0), 2);
+        assert_eq!(gutter_width(LineNumbers::Absolute, 9), 2);
+        assert_eq!(gutter_width(LineNumbers::Relative, 10), 3);
+        assert_eq!(gutter_width(LineNumbers::Hybrid, 12_345), 6);
+    }
+
+    #[test]
+    fn line_numbers_by_mode() {
+        // Line 5 and the cursor line 7, counted from zero.
+        assert_eq!(line_number(LineNumbers::Absolute, 5, 7), 6);
+        assert_eq!(line_number(LineNumbers::Relative, 5, 7), 2);
+        assert_eq!(line_number(LineNumbers::Relative, 9, 7), 2);
+        assert_eq!(line_number(LineNumbers::Relative, 7, 7), 0);
+        assert_eq!(line_number(LineNumbers::Hybrid, 5, 7), 2);
+        assert_eq!(line_number(LineNumbers::Hybrid, 7, 7), 8);
+    }
+
+    #[test]
+    fn kill_line_takes_the_line_break_after_blanks() {
+        let row = Row::from("text  ");
+        assert!(kill_line_end(&row, at(0, 3)) == at(6, 3));
+        assert!(kill_line_end(&row, at(3, 3)) == at(6, 3));
+        assert!(kill_line_end(&row, at(4, 3)) == at(0, 4));
+        assert!(kill_line_end(&row, at(6, 3)) == at(0, 4));
+        assert!(kill_line_end(&Row::default(), at(0, 0)) == at(0, 1));
+    }
+
+    #[test]
+    fn selections_run_from_the_earlier_end() {
+        assert!(ordered(at(5, 1), at(2, 3)) == (at(5, 1), at(2, 3)));
+        assert!(ordered(at(2, 3), at(5, 1)) == (at(5, 1), at(2, 3)));
+        assert!(ordered(at(4, 2), at(1, 2)) == (at(1, 2), at(4, 2)));
+    }
+
+    #[test]
+    fn selected_part_of_each_row() {
+        let (start, end) = (at(3, 1), at(2, 3));
+        assert_eq!(selected_in_row(start, end, 0, 10), None);
+        assert_eq!(selected_in_row(start, end, 1, 10), Some(3..10));
+        assert_eq!(selected_in_row(start, end, 2, 7), Some(0..7));
+        assert_eq!(selected_in_row(start, end, 3, 10), Some(0..2));
+        assert_eq!(selected_in_row(start, end, 4, 10), None);
+        assert_eq!(selected_in_row(at(1, 0), at(4, 0), 0, 10), Some(1..4));
+    }
+}
@@ -0,0 +1,312 @@
+use std::borrow::Cow;
+use std::io::{Error, ErrorKind};
+
+// Characters for the bytes 0x80 to 0x9f in Windows-1252. The five bytes it
+// leaves undefined decode to the control characters Latin-1 has there.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ',
+];
+
+// The encodings files are read and written in.
+#[derive(Default, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+    Windows1252,
+}
+
+// The names accepted by save_as_encoding, and shown in the status bar.
+const NAMES: &[(&str, Encoding)] = &[
+    ("UTF-8", Encoding::Utf8),
+    ("UTF-16LE", Encoding::Utf16Le),
+    ("UTF-16BE", Encoding::Utf16Be),
+    ("Latin-1", Encoding::Latin1),
+    ("Windows-1252", Encoding::Windows1252),
+];
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        NAMES
+            .iter()
+            .find(|(_, encoding)| *encoding == self)
+            .map_or("", |(name, _)| name)
+    }
+
+    // Case and dashes don't matter, so `utf16le` and `latin1` work too.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let simplify = |name: &str| name.to_lowercase().replace(['-', '_'], "");
+        let wanted = simplify(name);
+        NAMES
+            .iter()
+            .find(|(name, _)| simplify(name) == wanted)
+            .map(|(_, encoding)| *encoding)
+    }
+
+    pub fn names() -> Vec<&'static str> {
+        NAMES.iter().map(|(name, _)| *name).collect()
+    }
+
+    fn bom(self) -> &'static [u8] {
+        match self {
+            Self::Utf8 => b"\xef\xbb\xbf",
+            Self::Utf16Le => b"\xff\xfe",
+            Self::Utf16Be => b"\xfe\xff",
+            Self::Latin1 | Self::Windows1252 => b"",
+        }
+    }
+
+    // Whether files in this encoding normally start with a byte order mark.
+    pub fn wants_bom(self) -> bool {
+        matches!(self, Self::Utf16Le | Self::Utf16Be)
+    }
+}
+
+// A file's text and how it was stored.
+pub struct Decoded {
+    pub text: String,
+    pub encoding: Encoding,
+    // Whether the file started with a byte order mark, which isn't part of
+    // the text.
+    pub bom: bool,
+    // Whether some bytes weren't valid in the encoding and were replaced
+    // with U+FFFD, so that saving would not give back the same file.
+    pub lossy: bool,
+}
+
+// Works out the encoding of `bytes` and decodes them. A byte order mark
+// decides it if there is one; otherwise text with every other byte zero is
+// taken as UTF-16, valid UTF-8 as such, and anything else as Windows-1252,
+// which has a character for every byte.
+pub fn decode(bytes: &[u8]) -> Decoded {
+    for encoding in [Encoding::Utf8, Encoding::Utf16Le, Encoding::Utf16Be] {
+        if let Some(rest) = bytes.strip_prefix(encoding.bom()) {
+            let (text, lossy) = decode_as(rest, encoding);
+            return Decoded {
+                text,
+                encoding,
+                bom: true,
+                lossy,
+            };
+        }
+    }
+    // Zero bytes are valid UTF-8, so mostly-ASCII UTF-16 would pass as it.
+    let utf16 = if bytes.contains(&0) { guess_utf16(bytes) } else { None };
+    let encoding = if let Some(encoding) = utf16 {
+        encoding
+    } else if std::str::from_utf8(bytes).is_ok() {
+        Encoding::Utf8
+    } else {
+        Encoding::Windows1252
+    };
+    let (text, lossy) = decode_as(bytes, encoding);
+    Decoded {
+        text,
+        encoding,
+        bom: false,
+        lossy,
+    }
+}
+
+// Bytes that don't make sense in `encoding` become U+FFFD; the flag tells
+// whether there were any.
+#[allow(clippy::indexing_slicing)]
+fn decode_as(bytes: &[u8], encoding: Encoding) -> (String, bool) {
+    match encoding {
+        Encoding::Utf8 => match String::from_utf8_lossy(bytes) {
+            Cow::Borrowed(text) => (text.to_string(), false),
+            Cow::Owned(text) => (text, true),
+        },
+        Encoding::Utf16Le | Encoding::Utf16Be => {
+            let pairs = bytes.chunks_exact(2);
+            // An odd byte at the end is half a character.
+            let odd = !pairs.remainder().is_empty();
+            let units = pairs.map(|pair| match encoding {
+                Encoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+                _ => u16::from_be_bytes([pair[0], pair[1]]),
+            });
+            let mut lossy = odd;
+            let mut text: String = char::decode_utf16(units)
+                .map(|c| {
+                    c.unwrap_or_else(|_| {
+                        lossy = true;
+                        char::REPLACEMENT_CHARACTER
+                    })
+                })
+                .collect();
+            if odd {
+                text.push(char::REPLACEMENT_CHARACTER);
+            }
+            (text, lossy)
+        }
+        Encoding::Latin1 => (bytes.iter().map(|byte| char::from(*byte)).collect(), false),
+        Encoding::Windows1252 => (
+            bytes
+                .iter()
+                .map(|byte| match byte {
+                    0x80..=0x9f => WINDOWS_1252_HIGH[usize::from(byte.saturating_sub(0x80))],
+                    _ => char::from(*byte),
+                })
+                .collect(),
+            false,
+        ),
+    }
+}
+
+// ASCII text in UTF-16 has a zero in every other byte; most text has
+// enough of it to tell which half of each pair the zeros are in.
+#[allow(clippy::integer_arithmetic)]
+fn guess_utf16(bytes: &[u8]) -> Option<Encoding> {
+    if bytes.len() < 2 || !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let pairs = bytes.len() / 2;
+    let zeros_at = |index: usize| bytes.iter().skip(index).step_by(2).filter(|byte| **byte == 0).count();
+    let (even, odd) = (zeros_at(0), zeros_at(1));
+    // More than a third of the pairs, and hardly any zeros on the other side.
+    if odd.saturating_mul(3) > pairs && even.saturating_mul(10) < odd {
+        Some(Encoding::Utf16Le)
+    } else if even.saturating_mul(3) > pairs && odd.saturating_mul(10) < even {
+        Some(Encoding::Utf16Be)
+    } else {
+        None
+    }
+}
+
+// `text` in `encoding`, after a byte order mark if `bom` is set. Fails on
+// the first character the encoding has no bytes for, rather than losing it.
+pub fn encode(text: &str, encoding: Encoding, bom: bool) -> Result<Vec<u8>, Error> {
+    let mut bytes = Vec::with_capacity(text.len());
+    if bom {
+        bytes.extend_from_slice(encoding.bom());
+    }
+    match encoding {
+        Encoding::Utf8 => bytes.extend_from_slice(text.as_bytes()),
+        Encoding::Utf16Le => text.encode_utf16().for_each(|unit| bytes.extend(unit.to_le_bytes())),
+        Encoding::Utf16Be => text.encode_utf16().for_each(|unit| bytes.extend(unit.to_be_bytes())),
+        Encoding::Latin1 | Encoding::Windows1252 => {
+            for c in text.chars() {
+                let byte = single_byte(c, encoding).ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("{} has no character {c:?}", encoding.name()),
+                    )
+                })?;
+                bytes.push(byte);
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+fn single_byte(c: char, encoding: Encoding) -> Option<u8> {
+    if encoding == Encoding::Windows1252 {
+        if let Some(index) = WINDOWS_1252_HIGH.iter().position(|high| *high == c) {
+            return u8::try_from(index).ok().map(|index| index.saturating_add(0x80));
+        }
+        // Windows-1252 puts other characters where Latin-1 has these.
+        if ('\u{80}'..='\u{9f}').contains(&c) {
+            return None;
+        }
+    }
+    u8::try_from(u32::from(c)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "caf\u{e9} na\u{ef}ve\n";
+
+    #[test]
+    fn round_trips_every_encoding() {
+        for name in Encoding::names() {
+            let encoding = Encoding::from_name(name).unwrap();
+            for bom in [false, true] {
+                let bytes = encode(TEXT, encoding, bom).unwrap();
+                let decoded = decode(&bytes);
+                assert_eq!(decoded.text, TEXT, "{name}");
+                assert!(!decoded.lossy, "{name}");
+                // Without a byte order mark Latin-1 reads as Windows-1252,
+                // which agrees with it on these characters.
+                if encoding != Encoding::Latin1 {
+                    assert!(decoded.encoding == encoding, "{name}");
+                }
+                assert_eq!(decoded.bom, bom && !encoding.bom().is_empty(), "{name}");
+            }
+        }
+    }
+
+    #[test]
+    fn utf16_without_bom_is_recognised() {
+        let text = "hello\nworld\n";
+        for encoding in [Encoding::Utf16Le, Encoding::Utf16Be] {
+            let decoded = decode(&encode(text, encoding, false).unwrap());
+            assert_eq!(decoded.text, text);
+            assert!(decoded.encoding == encoding);
+            assert!(!decoded.bom);
+        }
+    }
+
+    #[test]
+    fn utf8_with_a_stray_zero_stays_utf8() {
+        let decoded = decode(b"abc\0def\n");
+        assert_eq!(decoded.text, "abc\0def\n");
+        assert!(decoded.encoding == Encoding::Utf8);
+    }
+
+    #[test]
+    fn invalid_utf8_falls_back_to_windows_1252() {
+        let decoded = decode(b"\x93quoted\x94 \x80 \xe9");
+        assert_eq!(decoded.text, "\u{201c}quoted\u{201d} \u{20ac} \u{e9}");
+        assert!(decoded.encoding == Encoding::Windows1252);
+        assert!(!decoded.bom && !decoded.lossy);
+    }
+
+    #[test]
+    fn bom_decides_the_encoding() {
+        let decoded = decode(b"\xef\xbb\xbfhi");
+        assert_eq!(decoded.text, "hi");
+        assert!(decoded.encoding == Encoding::Utf8 && decoded.bom);
+        let decoded = decode(b"\xfe\xff\x00h\x00i");
+        assert_eq!(decoded.text, "hi");
+        assert!(decoded.encoding == Encoding::Utf16Be && decoded.bom);
+    }
+
+    #[test]
+    fn bad_bytes_after_a_bom_are_lossy() {
+        let decoded = decode(b"\xef\xbb\xbfa\xffb");
+        assert_eq!(decoded.text, "a\u{fffd}b");
+        assert!(decoded.encoding == Encoding::Utf8 && decoded.lossy);
+        // An odd trailing byte.
+        let decoded = decode(b"\xff\xfeh\x00i");
+        assert_eq!(decoded.text, "h\u{fffd}");
+        assert!(decoded.encoding == Encoding::Utf16Le && decoded.lossy);
+        // A high surrogate with nothing after it.
+        let decoded = decode(b"\xfe\xff\x00h\xd8\x3d");
+        assert_eq!(decoded.text, "h\u{fffd}");
+        assert!(decoded.lossy);
+    }
+
+    #[test]
+    fn unencodable_characters_fail() {
+        assert!(encode("\u{20ac}", Encoding::Latin1, false).is_err());
+        assert_eq!(encode("\u{20ac}", Encoding::Windows1252, false).unwrap(), b"\x80");
+        assert!(encode("\u{80}", Encoding::Windows1252, false).is_err());
+        // The five bytes Windows-1252 leaves undefined round trip as controls.
+        assert_eq!(encode("\u{81}", Encoding::Windows1252, false).unwrap(), b"\x81");
+        assert!(encode("\u{3b1}", Encoding::Windows1252, false).is_err());
+        assert_eq!(encode("\u{3b1}", Encoding::Utf16Le, false).unwrap(), b"\xb1\x03");
+    }
+
+    #[test]
+    fn names_ignore_case_and_dashes() {
+        assert!(Encoding::from_name("utf16le") == Some(Encoding::Utf16Le));
+        assert!(Encoding::from_name("LATIN_1") == Some(Encoding::Latin1));
+        assert!(Encoding::from_name("ebcdic").is_none());
+        assert_eq!(Encoding::Windows1252.name(), "Windows-1252");
+    }
+}
@@ -0,0 +1,542 @@
+use crate::config::{config_dir, describe_toml_error};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone)]
+pub struct FileType {
+    name: String,
+    hl_opts: HighlightingOptions,
+}
+
+// What the highlighter looks for in a language. Everything is off by default,
+// which is what plain text gets.
+#[derive(Default, Clone)]
+pub struct HighlightingOptions {
+    numbers: bool,
+    // Characters that open and close a string, e.g. `"`.
+    strings: Vec<char>,
+    // Single quoted character literals such as `'a'` or `'\n'`.
+    characters: bool,
+    // Start of a comment that runs to the end of the line, e.g. `//`.
+    comment: Option<String>,
+    // Start and end of a comment that may span lines, e.g. `/*` and `*/`.
+    multiline_comment: Option<(String, String)>,
+    // Start and end of strings that may span lines, e.g. `"""` in Python.
+    multiline_strings: Vec<(String, String)>,
+    // Rust style raw strings: `r"..."`, `r#"..."#` and so on.
+    raw_strings: bool,
+    // Lines starting with this are headings, e.g. `#` in Markdown.
+    heading: Option<String>,
+    keywords: Vec<String>,
+    types: Vec<String>,
+}
+
+impl Default for FileType {
+    fn default() -> Self {
+        Self {
+            name: String::from("No filetype"),
+            hl_opts: HighlightingOptions::default(),
+        }
+    }
+}
+
+impl FileType {
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn highlighting_options(&self) -> &HighlightingOptions {
+        &self.hl_opts
+    }
+
+    // Picks the language from the file name's extension.
+    pub fn from(file_name: &str) -> Self {
+        let extension = Path::new(file_name)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default();
+        match extension {
+            "rs" => Self::rust(),
+            "c" | "h" => Self::c(),
+            "py" | "pyw" => Self::python(),
+            "json" => Self::json(),
+            "md" | "markdown" => Self::markdown(),
+            "toml" => Self::toml(),
+            _ => Self::default(),
+        }
+    }
+
+    fn rust() -> Self {
+        Self {
+            name: String::from("Rust"),
+            hl_opts: HighlightingOptions {
+                numbers: true,
+                // Rust strings can span lines.
+                strings: Vec::new(),
+                characters: true,
+                comment: Some(String::from("//")),
+                multiline_comment: Some(pair("/*", "*/")),
+                multiline_strings: vec![pair("\"", "\"")],
+                raw_strings: true,
+                heading: None,
+                keywords: words(&[
+                    "as", "break", "const", "continue", "crate", "else", "enum", "extern", "false",
+                    "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut",
+                    "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait",
+                    "true", "type", "unsafe", "use", "where", "while", "dyn", "async", "await",
+                ]),
+                types: words(&[
+                    "bool", "char", "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16", "u32",
+                    "u64", "u128", "usize", "f32", "f64", "str", "String", "Vec", "Option",
+                    "Result", "Box", "Some", "None", "Ok", "Err",
+                ]),
+            },
+        }
+    }
+
+    fn c() -> Self {
+        Self {
+            name: String::from("C"),
+            hl_opts: HighlightingOptions {
+                numbers: true,
+                strings: vec!['"'],
+                characters: true,
+                comment: Some(String::from("//")),
+                multiline_comment: Some(pair("/*", "*/")),
+                multiline_strings: Vec::new(),
+                raw_strings: false,
+                heading: None,
+                keywords: words(&[
+                    "auto", "break", "case", "const", "continue", "default", "do", "else", "enum",
+                    "extern", "for", "goto", "if", "inline", "register", "restrict", "return",
+                    "sizeof", "static", "struct", "switch", "typedef", "union", "volatile",
+                    "while", "NULL",
+                ]),
+                types: words(&[
+                    "char", "double", "float", "int", "long", "short", "signed", "unsigned",
+                    "void", "size_t", "bool",
+                ]),
+            },
+        }
+    }
+
+    fn python() -> Self {
+        Self {
+            name: String::from("Python"),
+            hl_opts: HighlightingOptions {
+                numbers: true,
+                strings: vec!['"', '\''],
+                characters: false,
+                comment: Some(String::from("#")),
+                multiline_comment: None,
+                multiline_strings: vec![pair("\"\"\"", "\"\"\""), pair("'''", "'''")],
+                raw_strings: false,
+                heading: None,
+                keywords: words(&[
+                    "and", "as", "assert", "async", "await", "break", "class", "continue", "def",
+                    "del", "elif", "else", "except", "False", "finally", "for", "from", "global",
+                    "if", "import", "in", "is", "lambda", "None", "nonlocal", "not", "or", "pass",
+                    "raise", "return", "True", "try", "while", "with", "yield", "self",
+                ]),
+                types: words(&[
+                    "bool", "bytes", "dict", "float", "int", "list", "object", "set", "str",
+                    "tuple",
+                ]),
+            },
+        }
+    }
+
+    fn json() -> Self {
+        Self {
+            name: String::from("JSON"),
+            hl_opts: HighlightingOptions {
+                numbers: true,
+                strings: vec!['"'],
+                keywords: words(&["true", "false", "null"]),
+                ..HighlightingOptions::default()
+            },
+        }
+    }
+
+    fn markdown() -> Self {
+        Self {
+            name: String::from("Markdown"),
+            hl_opts: HighlightingOptions {
+                // Inline code spans and fenced code blocks.
+                strings: vec!['`'],
+                multiline_strings: vec![pair("```", "```")],
+                heading: Some(String::from("#")),
+                ..HighlightingOptions::default()
+            },
+        }
+    }
+
+    fn toml() -> Self {
+        Self {
+            name: String::from("TOML"),
+            hl_opts: HighlightingOptions {
+                numbers: true,
+                strings: vec!['"', '\''],
+                comment: Some(String::from("#")),
+                multiline_strings: vec![pair("\"\"\"", "\"\"\""), pair("'''", "'''")],
+                // Table headers.
+                heading: Some(String::from("[")),
+                keywords: words(&["true", "false"]),
+                ..HighlightingOptions::default()
+            },
+        }
+    }
+}
+
+// Syntax definitions loaded from `<config dir>/syntax/*.toml`, checked before
+// the built-in ones.
+#[derive(Default)]
+pub struct FileTypes {
+    custom: Vec<(Vec<String>, FileType)>,
+}
+
+impl FileTypes {
+    // Loads every definition in the user's syntax directory. Broken files are
+    // skipped and described in the returned errors.
+    pub fn load() -> (Self, Vec<String>) {
+        match config_dir() {
+            Some(dir) => Self::load_from(&dir.join("syntax")),
+            None => (Self::default(), Vec::new()),
+        }
+    }
+
+    pub fn load_from(dir: &Path) -> (Self, Vec<String>) {
+        let mut file_types = Self::default();
+        let mut errors = Vec::new();
+        let Ok(entries) = fs::read_dir(dir) else {
+            // No syntax directory is fine; there's just nothing to add.
+            return (file_types, errors);
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension == "toml"))
+            .collect();
+        paths.sort();
+        for path in paths {
+            let file_name = path
+                .file_name()
+                .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+            let definition = fs::read_to_string(&path)
+                .map_err(|error| error.to_string())
+                .and_then(|contents| {
+                    toml::from_str::<SyntaxDefinition>(&contents)
+                        .map_err(|error| describe_toml_error(&error, &contents))
+                })
+                .and_then(SyntaxDefinition::into_file_type);
+            match definition {
+                Ok(definition) => file_types.custom.push(definition),
+                Err(error) => errors.push(format!("{file_name}: {error}")),
+            }
+        }
+        (file_types, errors)
+    }
+
+    // Picks the language for `file_name`, preferring the user's definitions
+    // and falling back to plain text when nothing matches.
+    pub fn detect(&self, file_name: &str) -> FileType {
+        let extension = Path::new(file_name)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default();
+        self.custom
+            .iter()
+            .find(|(extensions, _)| extensions.iter().any(|known| known == extension))
+            .map_or_else(|| FileType::from(file_name), |(_, file_type)| file_type.clone())
+    }
+}
+
+// The on-disk form of a syntax definition.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SyntaxDefinition {
+    name: String,
+    extensions: Vec<String>,
+    #[serde(default)]
+    numbers: bool,
+    #[serde(default)]
+    strings: Vec<String>,
+    #[serde(default)]
+    characters: bool,
+    comment: Option<String>,
+    multiline_comment: Option<Vec<String>>,
+    #[serde(default)]
+    multiline_strings: Vec<Vec<String>>,
+    #[serde(default)]
+    raw_strings: bool,
+    heading: Option<String>,
+    #[serde(default)]
+    keywords: Vec<String>,
+    #[serde(default)]
+    types: Vec<String>,
+}
+
+impl SyntaxDefinition {
+    fn into_file_type(self) -> Result<(Vec<String>, FileType), String> {
+        if self.name.trim().is_empty() {
+            return Err("`name` must not be empty".to_string());
+        }
+        if self.extensions.is_empty() {
+            return Err("`extensions` needs at least one extension".to_string());
+        }
+        let mut strings = Vec::new();
+        for delimiter in &self.strings {
+            let mut chars = delimiter.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => strings.push(c),
+                _ => return Err(format!("string delimiter {delimiter:?} must be a single character")),
+            }
+        }
+        let multiline_comment = self
+            .multiline_comment
+            .map(|markers| delimiter_pair("multiline_comment", markers))
+            .transpose()?;
+        let multiline_strings = self
+            .multiline_strings
+            .into_iter()
+            .map(|markers| delimiter_pair("multiline_strings", markers))
+            .collect::<Result<Vec<_>, _>>()?;
+        if self.comment.as_deref() == Some("") || self.heading.as_deref() == Some("") {
+            return Err("`comment` and `heading` must not be empty".to_string());
+        }
+        let extensions = self
+            .extensions
+            .into_iter()
+            .map(|extension| extension.trim_start_matches('.').to_string())
+            .collect();
+        Ok((
+            extensions,
+            FileType {
+                name: self.name,
+                hl_opts: HighlightingOptions {
+                    numbers: self.numbers,
+                    strings,
+                    characters: self.characters,
+                    comment: self.comment,
+                    multiline_comment,
+                    multiline_strings,
+                    raw_strings: self.raw_strings,
+                    heading: self.heading,
+                    keywords: self.keywords,
+                    types: self.types,
+                },
+            },
+        ))
+    }
+}
+
+fn delimiter_pair(field: &str, markers: Vec<String>) -> Result<(String, String), String> {
+    match <[String; 2]>::try_from(markers) {
+        Ok([start, end]) if !start.is_empty() && !end.is_empty() => Ok((start, end)),
+        _ => Err(format!("`{field}` entries must be a pair of non-empty markers, like [\"/*\", \"*/\"]")),
+    }
+}
+
+impl HighlightingOptions {
+    pub fn numbers(&self) -> bool {
+        self.numbers
+    }
+    pub fn strings(&self) -> &[char] {
+        &self.strings
+    }
+    pub fn characters(&self) -> bool {
+        self.characters
+    }
+    pub fn comment(&self) -> Option<&str> {
+        self.comment.as_deref()
+    }
+    pub fn multiline_comment(&self) -> Option<(&str, &str)> {
+        self.multiline_comment
+            .as_ref()
+            .map(|(start, end)| (start.as_str(), end.as_str()))
+    }
+    pub fn multiline_strings(&self) -> &[(String, String)] {
+        &self.multiline_strings
+    }
+    pub fn raw_strings(&self) -> bool {
+        self.raw_strings
+    }
+    // Whether `line` is a heading. A `[` heading is a TOML style table
+    // header, `[name]` or `[[name]]`, so that arrays don't count.
+    pub fn is_heading(&self, line: &str) -> bool {
+        let line = line.trim_start();
+        match self.heading.as_deref() {
+            Some("[") => is_table_header(line),
+            Some(heading) => line.starts_with(heading),
+            None => false,
+        }
+    }
+    pub fn keywords(&self) -> &[String] {
+        &self.keywords
+    }
+    pub fn types(&self) -> &[String] {
+        &self.types
+    }
+}
+
+// `[name]` or `[[name]]`, where the name is made of bare or quoted keys
+// joined by dots, optionally followed by a comment.
+fn is_table_header(line: &str) -> bool {
+    let (brackets, close) = if line.starts_with("[[") { (2, "]]") } else { (1, "]") };
+    let Some(rest) = line.get(brackets..) else {
+        return false;
+    };
+    let mut quote = None;
+    for (index, c) in rest.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == ']' => {
+                let name = &rest[..index];
+                let after = rest[index..].strip_prefix(close).map(str::trim_start);
+                return !name.trim().is_empty() && after.is_some_and(|after| after.is_empty() || after.starts_with('#'));
+            }
+            None if c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ' ' | '\t') => {}
+            None => return false,
+        }
+    }
+    false
+}
+
+fn words(words: &[&str]) -> Vec<String> {
+    words.iter().map(|word| (*word).to_string()).collect()
+}
+
+fn pair(start: &str, end: &str) -> (String, String) {
+    (start.to_string(), end.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    // Loads syntax definitions from a scratch directory holding `files`.
+    fn load(name: &str, files: &[(&str, &str)]) -> (FileTypes, Vec<String>) {
+        let dir = std::env::temp_dir().join(format!("hecto-test-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (file_name, contents) in files {
+            fs::write(dir.join(file_name), contents).unwrap();
+        }
+        let loaded = FileTypes::load_from(&dir);
+        let _ = fs::remove_dir_all(&dir);
+        loaded
+    }
+
+    #[test]
+    fn user_definitions_come_before_the_built_in_ones() {
+        let (file_types, errors) = load(
+            "override",
+            &[
+                ("rust.toml", "name = \"My Rust\"\nextensions = [\".rs\", \"rlib\"]\nkeywords = [\"fn\"]\n"),
+                ("notes.txt", "not a definition"),
+            ],
+        );
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(file_types.detect("main.rs").name(), "My Rust");
+        assert_eq!(file_types.detect("x.rlib").name(), "My Rust");
+        assert_eq!(file_types.detect("main.py").name(), "Python");
+    }
+
+    #[test]
+    fn a_missing_directory_is_not_an_error() {
+        let dir = std::env::temp_dir().join(format!("hecto-test-{}-missing", process::id()));
+        let (file_types, errors) = FileTypes::load_from(&dir);
+        assert!(errors.is_empty());
+        assert_eq!(file_types.detect("main.rs").name(), "Rust");
+    }
+
+    #[test]
+    fn broken_definitions_are_skipped_and_reported() {
+        let (file_types, errors) = load(
+            "broken",
+            &[
+                ("a.toml", "name = \"A\"\nextensions = [\"a\"\n"),
+                ("b.toml", "name = \"B\"\nextensions = [\"b\"]\ncolour = true\n"),
+                ("c.toml", "name = \" \"\nextensions = [\"c\"]\n"),
+                ("d.toml", "name = \"D\"\nextensions = []\n"),
+                ("e.toml", "name = \"E\"\nextensions = [\"e\"]\nstrings = [\"''\"]\n"),
+                ("f.toml", "name = \"F\"\nextensions = [\"f\"]\nmultiline_comment = [\"/*\"]\n"),
+                ("g.toml", "name = \"G\"\nextensions = [\"g\"]\ncomment = \"\"\n"),
+                ("h.toml", "name = \"H\"\nextensions = [\"h2\"]\n"),
+            ],
+        );
+        assert_eq!(errors.len(), 7, "{errors:?}");
+        for (error, file) in errors.iter().zip("abcdefg".chars()) {
+            assert!(error.starts_with(&format!("{file}.toml: ")), "{error}");
+        }
+        assert!(errors[1].contains("colour"), "{}", errors[1]);
+        assert!(errors[2].contains("`name`"), "{}", errors[2]);
+        assert!(errors[3].contains("`extensions`"), "{}", errors[3]);
+        assert!(errors[4].contains("single character"), "{}", errors[4]);
+        assert!(errors[5].contains("`multiline_comment`"), "{}", errors[5]);
+        assert!(errors[6].contains("`comment`"), "{}", errors[6]);
+        // The good file still loads.
+        assert_eq!(file_types.detect("x.h2").name(), "H");
+        assert_eq!(file_types.detect("x.a").name(), "No filetype");
+    }
+
+    #[test]
+    fn extensions_pick_the_built_in_languages() {
+        for (file_name, name) in [
+            ("main.rs", "Rust"),
+            ("lib.h", "C"),
+            ("script.pyw", "Python"),
+            ("data.json", "JSON"),
+            ("README.markdown", "Markdown"),
+            ("Cargo.toml", "TOML"),
+            ("notes.txt", "No filetype"),
+            ("Makefile", "No filetype"),
+        ] {
+            assert_eq!(FileType::from(file_name).name(), name, "{file_name}");
+        }
+    }
+
+    #[test]
+    fn built_in_definitions() {
+        let rust = FileType::from("main.rs");
+        let rust = rust.highlighting_options();
+        assert!(rust.raw_strings() && rust.characters());
+        assert_eq!(rust.multiline_comment(), Some(("/*", "*/")));
+        assert!(rust.keywords().iter().any(|keyword| keyword == "fn"));
+        assert!(rust.types().iter().any(|name| name == "usize"));
+        let python = FileType::from("main.py");
+        let python = python.highlighting_options();
+        assert_eq!(python.comment(), Some("#"));
+        assert!(!python.characters());
+        assert_eq!(python.multiline_strings().len(), 2);
+        let json = FileType::from("data.json");
+        assert!(json.highlighting_options().comment().is_none());
+        // Plain text highlights nothing.
+        let text = FileType::default();
+        let text = text.highlighting_options();
+        assert!(!text.numbers() && text.strings().is_empty() && text.keywords().is_empty());
+        assert!(!text.is_heading("# title"));
+    }
+
+    #[test]
+    fn markdown_headings() {
+        let markdown = FileType::from("README.md");
+        assert!(markdown.highlighting_options().is_heading("## Usage"));
+        assert!(!markdown.highlighting_options().is_heading("Usage #1"));
+    }
+
+    #[test]
+    fn toml_headings_are_table_headers() {
+        let toml = FileType::from("Cargo.toml");
+        let toml = toml.highlighting_options();
+        for line in ["[package]", "  [dependencies.serde]", "[[bin]]", "[\"a,b\".c] # note", "[ a ]"] {
+            assert!(toml.is_heading(line), "{line}");
+        }
+        for line in ["[1, 2],", "[\"a\", \"b\"]", "[]", "[[a]", "[a]]", "[a] = 1", "key = [1]", "[a"] {
+            assert!(!toml.is_heading(line), "{line}");
+        }
+    }
+}
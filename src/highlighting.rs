@@ -0,0 +1,23 @@
+#[derive(PartialEq, Clone, Copy)]
+pub enum Type {
+    None,
+    Number,
+    Match,
+    String,
+    Character,
+    Comment,
+    Keyword,
+    Type,
+    Heading,
+}
+
+// A construct that is still open at the end of a row, and so colors the
+// start of the next one.
+#[derive(PartialEq, Clone, Default)]
+pub enum Open {
+    #[default]
+    None,
+    Comment,
+    // `close` ends the string; raw strings don't have `escapes`.
+    String { close: String, escapes: bool },
+}
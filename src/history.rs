@@ -0,0 +1,589 @@
+use crate::Position;
+use std::fmt::Write;
+use unicode_segmentation::UnicodeSegmentation;
+
+const FILE_HEADER: &str = "hecto-undo 1";
+
+// A single change to the document. Everything needed to both apply and
+// revert the change is stored, so the journal never has to look at the rows.
+#[derive(Clone)]
+pub enum Edit {
+    // `text` was inserted into the row at `at` (never contains a newline).
+    Insert { at: Position, text: String },
+    // `text` was removed from the row at `at` (never contains a newline).
+    Delete { at: Position, text: String },
+    // The row was split at `at`. If `at.y` was past the last row, an empty
+    // row was appended instead.
+    Split { at: Position },
+    // The row below `at.y` was appended to it; `at.x` is where it was joined.
+    Join { at: Position },
+}
+
+impl Edit {
+    pub fn at(&self) -> Position {
+        match self {
+            Edit::Insert { at, .. }
+            | Edit::Delete { at, .. }
+            | Edit::Split { at }
+            | Edit::Join { at } => *at,
+        }
+    }
+}
+
+// The edits of each step `History::travel` passes, in order, flagged with
+// whether they have to be reverted.
+pub type TravelSteps = Vec<(Vec<Edit>, bool)>;
+
+// Edits that are undone and redone together, plus where the cursor was
+// before and after them.
+#[derive(Default)]
+struct Step {
+    edits: Vec<Edit>,
+    before: Position,
+    after: Position,
+}
+
+// One state of the document in the undo tree. The state is reached from its
+// parent by applying `step`.
+#[derive(Default)]
+struct Node {
+    parent: usize,
+    children: Vec<usize>,
+    // The child that redo moves to: the one most recently created or left.
+    redo_child: Option<usize>,
+    step: Step,
+}
+
+// The edit history of a document, kept as a tree: undoing and then typing
+// something new starts a branch instead of throwing the undone edits away.
+// Node 0 is the state the document was opened in, and nodes are numbered in
+// the order they were created, which is what time travel walks through.
+pub struct History {
+    nodes: Vec<Node>,
+    current: usize,
+    // The node that matches the file on disk, if any.
+    saved: Option<usize>,
+    // When set, the next edit always starts a new step.
+    sealed: bool,
+    group_depth: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            nodes: vec![Node::default()],
+            current: 0,
+            saved: Some(0),
+            sealed: true,
+            group_depth: 0,
+        }
+    }
+}
+
+impl History {
+    pub fn record(&mut self, edit: Edit, before: Position, after: Position) {
+        let can_extend = self.current != 0 && !self.sealed;
+        if let Some(node) = self.nodes.get_mut(self.current) {
+            if can_extend && node.children.is_empty() {
+                let merged = if self.group_depth > 0 {
+                    node.step.edits.push(edit.clone());
+                    true
+                } else {
+                    Self::merge(&mut node.step, &edit)
+                };
+                if merged {
+                    node.step.after = after;
+                    return;
+                }
+            }
+        }
+        self.record_step(vec![edit], before, after);
+    }
+
+    // Starts a new step made of `edits`, without trying to merge them into
+    // the previous one. If the current state already has children, this
+    // starts a new branch next to them.
+    pub fn record_step(&mut self, edits: Vec<Edit>, before: Position, after: Position) {
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            parent: self.current,
+            children: Vec::new(),
+            redo_child: None,
+            step: Step {
+                edits,
+                before,
+                after,
+            },
+        });
+        if let Some(parent) = self.nodes.get_mut(self.current) {
+            parent.children.push(id);
+            parent.redo_child = Some(id);
+        }
+        self.current = id;
+        self.sealed = false;
+    }
+
+    // Consecutive typing and consecutive deletes in the same row are merged
+    // into one step per word, so undo takes back a word at a time.
+    #[allow(clippy::integer_arithmetic)]
+    fn merge(last: &mut Step, edit: &Edit) -> bool {
+        // Typing past the last row adds the row first; the typing that
+        // follows belongs with it.
+        if let (Some(Edit::Split { at }), Edit::Insert { at: new_at, .. }) = (last.edits.last(), edit) {
+            if at == new_at {
+                last.edits.push(edit.clone());
+                return true;
+            }
+        }
+        match (last.edits.last_mut(), edit) {
+            (Some(Edit::Insert { at, text }), Edit::Insert { at: new_at, text: new_text }) => {
+                if at.y == new_at.y
+                    && at.x + text[..].graphemes(true).count() == new_at.x
+                    && !starts_word(text, new_text)
+                {
+                    text.push_str(new_text);
+                    return true;
+                }
+                false
+            }
+            (Some(Edit::Delete { at, text }), Edit::Delete { at: new_at, text: new_text }) => {
+                if at.y != new_at.y {
+                    return false;
+                }
+                if at.x == new_at.x && !starts_word(text, new_text) {
+                    // Delete key: the removed text grows to the right.
+                    text.push_str(new_text);
+                    return true;
+                }
+                if new_at.x + new_text[..].graphemes(true).count() == at.x && !starts_word(new_text, text) {
+                    // Backspace: the removed text grows to the left.
+                    text.insert_str(0, new_text);
+                    at.x = new_at.x;
+                    return true;
+                }
+                false
+            }
+            _ => false,
+        }
+    }
+
+    // Edits recorded between `begin_group` and `end_group` form one step.
+    pub fn begin_group(&mut self) {
+        if self.group_depth == 0 {
+            self.sealed = true;
+        }
+        self.group_depth = self.group_depth.saturating_add(1);
+    }
+
+    pub fn end_group(&mut self) {
+        self.group_depth = self.group_depth.saturating_sub(1);
+        if self.group_depth == 0 {
+            self.sealed = true;
+        }
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.saved = Some(self.current);
+        self.sealed = true;
+    }
+
+    pub fn is_at_saved(&self) -> bool {
+        self.saved == Some(self.current)
+    }
+
+    // The current state and the number of states, for status messages.
+    pub fn state(&self) -> (usize, usize) {
+        (self.current, self.nodes.len().saturating_sub(1))
+    }
+
+    // Moves to the parent state and returns the edits to revert together
+    // with the cursor position from before them.
+    pub fn undo(&mut self) -> Option<(Vec<Edit>, Position)> {
+        if self.current == 0 {
+            return None;
+        }
+        let id = self.current;
+        let node = self.nodes.get(id)?;
+        let result = (node.step.edits.clone(), node.step.before);
+        let parent = node.parent;
+        if let Some(parent) = self.nodes.get_mut(parent) {
+            parent.redo_child = Some(id);
+        }
+        self.current = parent;
+        self.sealed = true;
+        Some(result)
+    }
+
+    // Moves to the most recent child state and returns the edits to apply
+    // together with the cursor position from after them.
+    pub fn redo(&mut self) -> Option<(Vec<Edit>, Position)> {
+        let id = self.nodes.get(self.current)?.redo_child?;
+        let node = self.nodes.get(id)?;
+        self.current = id;
+        self.sealed = true;
+        Some((node.step.edits.clone(), node.step.after))
+    }
+
+    // Moves to the state created just before (or after) the current one,
+    // regardless of which branch it is on. Returns every step on the way and
+    // the cursor position to end up at.
+    pub fn travel(&mut self, forward: bool) -> Option<(TravelSteps, Position)> {
+        let target = if forward {
+            self.current.saturating_add(1)
+        } else {
+            self.current.checked_sub(1)?
+        };
+        if target >= self.nodes.len() {
+            return None;
+        }
+        let from_path = self.path_to_root(self.current);
+        let to_path = self.path_to_root(target);
+        let common = *from_path.iter().find(|id| to_path.contains(id))?;
+
+        let mut steps = Vec::new();
+        let mut cursor = Position::default();
+        while self.current != common {
+            let (edits, before) = self.undo()?;
+            steps.push((edits, true));
+            cursor = before;
+        }
+        let mut down: Vec<usize> = to_path.into_iter().take_while(|&id| id != common).collect();
+        down.reverse();
+        for id in down {
+            if let Some(node) = self.nodes.get_mut(self.current) {
+                node.redo_child = Some(id);
+            }
+            let (edits, after) = self.redo()?;
+            steps.push((edits, false));
+            cursor = after;
+        }
+        Some((steps, cursor))
+    }
+
+    // `id` followed by all of its ancestors, ending with the root.
+    fn path_to_root(&self, mut id: usize) -> Vec<usize> {
+        let mut path = vec![id];
+        while id != 0 {
+            id = self.nodes.get(id).map_or(0, |node| node.parent);
+            path.push(id);
+        }
+        path
+    }
+
+    // Writes the tree in a line based text format. `hash` identifies the
+    // file content the current state corresponds to.
+    pub fn serialize(&self, hash: u64) -> String {
+        let mut result = String::new();
+        let _ = writeln!(result, "{FILE_HEADER}");
+        let _ = writeln!(result, "hash {hash:016x}");
+        let _ = writeln!(result, "current {}", self.current);
+        for node in self.nodes.iter().skip(1) {
+            let Step {
+                edits,
+                before,
+                after,
+            } = &node.step;
+            let _ = writeln!(
+                result,
+                "node {} {} {} {} {} {}",
+                node.parent,
+                before.x,
+                before.y,
+                after.x,
+                after.y,
+                edits.len()
+            );
+            for edit in edits {
+                let _ = match edit {
+                    Edit::Insert { at, text } => {
+                        writeln!(result, "insert {} {} {}", at.x, at.y, escape(text))
+                    }
+                    Edit::Delete { at, text } => {
+                        writeln!(result, "delete {} {} {}", at.x, at.y, escape(text))
+                    }
+                    Edit::Split { at } => writeln!(result, "split {} {}", at.x, at.y),
+                    Edit::Join { at } => writeln!(result, "join {} {}", at.x, at.y),
+                };
+            }
+        }
+        result
+    }
+
+    // Reads a tree written by `serialize`. Returns `None` if the contents are
+    // malformed or were written for a different version of the file. The
+    // restored current state is considered saved.
+    pub fn deserialize(contents: &str, hash: u64) -> Option<Self> {
+        let mut lines = contents.lines();
+        if lines.next()? != FILE_HEADER {
+            return None;
+        }
+        let stored_hash = u64::from_str_radix(lines.next()?.strip_prefix("hash ")?, 16).ok()?;
+        if stored_hash != hash {
+            return None;
+        }
+        let current: usize = lines.next()?.strip_prefix("current ")?.parse().ok()?;
+
+        let mut history = Self::default();
+        while let Some(line) = lines.next() {
+            let fields: Vec<usize> = line
+                .strip_prefix("node ")?
+                .split(' ')
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .ok()?;
+            let [parent, before_x, before_y, after_x, after_y, count] = fields[..] else {
+                return None;
+            };
+            if parent >= history.nodes.len() {
+                return None;
+            }
+            let mut edits = Vec::new();
+            for _ in 0..count {
+                edits.push(parse_edit(lines.next()?)?);
+            }
+            let id = history.nodes.len();
+            history.nodes.push(Node {
+                parent,
+                children: Vec::new(),
+                redo_child: None,
+                step: Step {
+                    edits,
+                    before: Position {
+                        x: before_x,
+                        y: before_y,
+                    },
+                    after: Position {
+                        x: after_x,
+                        y: after_y,
+                    },
+                },
+            });
+            let parent = history.nodes.get_mut(parent)?;
+            parent.children.push(id);
+            parent.redo_child = Some(id);
+        }
+        if current >= history.nodes.len() {
+            return None;
+        }
+        // Make redo follow the branch that leads to the current state.
+        let path = history.path_to_root(current);
+        for pair in path.windows(2) {
+            if let [child, parent] = *pair {
+                history.nodes.get_mut(parent)?.redo_child = Some(child);
+            }
+        }
+        history.current = current;
+        history.saved = Some(current);
+        Some(history)
+    }
+}
+
+fn parse_edit(line: &str) -> Option<Edit> {
+    let mut fields = line.splitn(4, ' ');
+    let kind = fields.next()?;
+    let at = Position {
+        x: fields.next()?.parse().ok()?,
+        y: fields.next()?.parse().ok()?,
+    };
+    Some(match kind {
+        "insert" => Edit::Insert {
+            at,
+            text: unescape(fields.next()?)?,
+        },
+        "delete" => Edit::Delete {
+            at,
+            text: unescape(fields.next()?)?,
+        },
+        "split" => Edit::Split { at },
+        "join" => Edit::Join { at },
+        _ => return None,
+    })
+}
+
+// Whether `after`, following `before`, begins a new word.
+fn starts_word(before: &str, after: &str) -> bool {
+    before.chars().last().is_some_and(char::is_whitespace) && after.chars().next().is_some_and(|c| !c.is_whitespace())
+}
+
+// Keeps every edit on a single line of the history file.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn unescape(text: &str) -> Option<String> {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            result.push(match chars.next()? {
+                'n' => '\n',
+                'r' => '\r',
+                '\\' => '\\',
+                _ => return None,
+            });
+        } else {
+            result.push(c);
+        }
+    }
+    Some(result)
+}
+
+// FNV-1a; stable across builds, unlike `DefaultHasher`.
+pub fn content_hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(x: usize, y: usize) -> Position {
+        Position { x, y }
+    }
+
+    fn insert(x: usize, y: usize, text: &str) -> Edit {
+        Edit::Insert {
+            at: at(x, y),
+            text: text.to_string(),
+        }
+    }
+
+    // Two branches off the first step, with text that needs escaping.
+    fn branched() -> History {
+        let mut history = History::default();
+        history.record_step(vec![insert(0, 0, "one")], at(0, 0), at(3, 0));
+        history.record_step(
+            vec![
+                Edit::Split { at: at(3, 0) },
+                insert(0, 1, "back\\slash"),
+            ],
+            at(3, 0),
+            at(10, 1),
+        );
+        history.undo();
+        history.record_step(
+            vec![Edit::Delete {
+                at: at(1, 0),
+                text: "n\r\n".to_string(),
+            }],
+            at(1, 0),
+            at(1, 0),
+        );
+        history.record_step(vec![Edit::Join { at: at(2, 0) }], at(2, 0), at(2, 0));
+        history
+    }
+
+    #[test]
+    fn round_trip_keeps_the_tree() {
+        let history = branched();
+        let saved = history.serialize(42);
+        let restored = History::deserialize(&saved, 42).unwrap();
+        assert_eq!(restored.serialize(42), saved);
+        assert_eq!(restored.state(), (4, 4));
+        assert!(restored.is_at_saved());
+    }
+
+    #[test]
+    fn round_trip_keeps_escaped_text() {
+        let saved = branched().serialize(1);
+        let mut restored = History::deserialize(&saved, 1).unwrap();
+        restored.undo();
+        let (edits, before) = restored.undo().unwrap();
+        assert!(matches!(
+            edits.as_slice(),
+            [Edit::Delete { text, .. }] if text == "n\r\n"
+        ));
+        assert!(before == at(1, 0));
+    }
+
+    #[test]
+    fn redo_follows_the_restored_branch() {
+        let saved = branched().serialize(7);
+        let mut restored = History::deserialize(&saved, 7).unwrap();
+        while restored.undo().is_some() {}
+        restored.redo();
+        restored.redo();
+        assert_eq!(restored.state(), (3, 4));
+    }
+
+    #[test]
+    fn travel_crosses_branches_after_restoring() {
+        let saved = branched().serialize(7);
+        let mut restored = History::deserialize(&saved, 7).unwrap();
+        restored.travel(false);
+        restored.travel(false);
+        let (steps, cursor) = restored.travel(false).unwrap();
+        // From state 2 on one branch back to state 1 is just one revert.
+        assert_eq!(restored.state(), (1, 4));
+        assert_eq!(steps.len(), 1);
+        assert!(cursor == at(3, 0));
+    }
+
+    // Records typing `text` one character at a time from the start of row 0.
+    fn type_text(history: &mut History, text: &str) {
+        for (x, c) in text.chars().enumerate() {
+            history.record(insert(x, 0, &c.to_string()), at(x, 0), at(x + 1, 0));
+        }
+    }
+
+    #[test]
+    fn typing_merges_by_word() {
+        let mut history = History::default();
+        type_text(&mut history, "one two  three");
+        assert_eq!(history.state(), (3, 3));
+        let (edits, before) = history.undo().unwrap();
+        assert!(matches!(edits.as_slice(), [Edit::Insert { text, .. }] if text == "three"));
+        assert!(before == at(9, 0));
+        let (edits, _) = history.undo().unwrap();
+        assert!(matches!(edits.as_slice(), [Edit::Insert { text, .. }] if text == "two  "));
+    }
+
+    #[test]
+    fn backspacing_merges_by_word() {
+        let mut history = History::default();
+        history.mark_saved();
+        for x in (0..7).rev() {
+            let text = "one two".chars().nth(x).unwrap().to_string();
+            history.record(Edit::Delete { at: at(x, 0), text }, at(x + 1, 0), at(x, 0));
+        }
+        let (edits, _) = history.undo().unwrap();
+        assert!(matches!(edits.as_slice(), [Edit::Delete { text, .. }] if text == "one "));
+        let (edits, _) = history.undo().unwrap();
+        assert!(matches!(edits.as_slice(), [Edit::Delete { text, .. }] if text == "two"));
+    }
+
+    #[test]
+    fn different_hash_is_rejected() {
+        let saved = branched().serialize(1);
+        assert!(History::deserialize(&saved, 2).is_none());
+    }
+
+    #[test]
+    fn malformed_contents_are_rejected() {
+        let header = format!("{FILE_HEADER}\nhash {:016x}\n", 5);
+        for body in [
+            "current 0\nnode 0 0 0 0 0 99999999999\n",
+            "current 0\nnode 0 0 0 0 0 1\n",
+            "current 0\nnode 3 0 0 0 0 0\n",
+            "current 2\nnode 0 0 0 0 0 0\n",
+            "current 1\nnode 0 0 0 0 0 1\ninsert 0 0 bad\\escape\n",
+            "current 1\nnode 0 0 0 0 1\n",
+        ] {
+            assert!(History::deserialize(&format!("{header}{body}"), 5).is_none(), "{body}");
+        }
+        assert!(History::deserialize("hecto-undo 0\n", 5).is_none());
+    }
+
+    #[test]
+    fn content_hash_is_stable() {
+        assert_eq!(content_hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(content_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+}
@@ -0,0 +1,555 @@
+use std::collections::{BTreeMap, HashMap};
+use termion::event::Key;
+
+// Everything the editor can be told to do from the keyboard. Typing a
+// character that isn't bound to anything inserts it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Command {
+    Quit,
+    Save,
+    Find,
+    FindRegex,
+    Replace,
+    Undo,
+    Redo,
+    HistoryBack,
+    HistoryForward,
+    ToggleMark,
+    ClearMark,
+    Copy,
+    Cut,
+    Paste,
+    Delete,
+    Backspace,
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    PageUp,
+    PageDown,
+    LineStart,
+    LineEnd,
+    SelectUp,
+    SelectDown,
+    SelectLeft,
+    SelectRight,
+    KillLine,
+    YankPop,
+    OpenFile,
+    NextBuffer,
+    PreviousBuffer,
+    ListBuffers,
+    CloseBuffer,
+    SplitHorizontal,
+    SplitVertical,
+    NextPane,
+    PreviousPane,
+    GrowPane,
+    ShrinkPane,
+    ClosePane,
+    ToggleLineNumbers,
+    ToggleWrap,
+    ConvertLineEndings,
+    SaveAsEncoding,
+}
+
+// The names used for commands in the `[keys]` section of the config.
+const COMMANDS: &[(&str, Command)] = &[
+    ("quit", Command::Quit),
+    ("save", Command::Save),
+    ("find", Command::Find),
+    ("find_regex", Command::FindRegex),
+    ("replace", Command::Replace),
+    ("undo", Command::Undo),
+    ("redo", Command::Redo),
+    ("history_back", Command::HistoryBack),
+    ("history_forward", Command::HistoryForward),
+    ("toggle_mark", Command::ToggleMark),
+    ("clear_mark", Command::ClearMark),
+    ("copy", Command::Copy),
+    ("cut", Command::Cut),
+    ("paste", Command::Paste),
+    ("delete", Command::Delete),
+    ("backspace", Command::Backspace),
+    ("move_up", Command::MoveUp),
+    ("move_down", Command::MoveDown),
+    ("move_left", Command::MoveLeft),
+    ("move_right", Command::MoveRight),
+    ("page_up", Command::PageUp),
+    ("page_down", Command::PageDown),
+    ("line_start", Command::LineStart),
+    ("line_end", Command::LineEnd),
+    ("select_up", Command::SelectUp),
+    ("select_down", Command::SelectDown),
+    ("select_left", Command::SelectLeft),
+    ("select_right", Command::SelectRight),
+    ("kill_line", Command::KillLine),
+    ("yank_pop", Command::YankPop),
+    ("open_file", Command::OpenFile),
+    ("next_buffer", Command::NextBuffer),
+    ("previous_buffer", Command::PreviousBuffer),
+    ("list_buffers", Command::ListBuffers),
+    ("close_buffer", Command::CloseBuffer),
+    ("split_horizontal", Command::SplitHorizontal),
+    ("split_vertical", Command::SplitVertical),
+    ("next_pane", Command::NextPane),
+    ("previous_pane", Command::PreviousPane),
+    ("grow_pane", Command::GrowPane),
+    ("shrink_pane", Command::ShrinkPane),
+    ("close_pane", Command::ClosePane),
+    ("toggle_line_numbers", Command::ToggleLineNumbers),
+    ("toggle_wrap", Command::ToggleWrap),
+    ("convert_line_endings", Command::ConvertLineEndings),
+    ("save_as_encoding", Command::SaveAsEncoding),
+];
+
+const DEFAULT_BINDINGS: &[(&[Key], Command)] = &[
+    (&[Key::Ctrl('q')], Command::Quit),
+    (&[Key::Ctrl('s')], Command::Save),
+    (&[Key::Ctrl('f')], Command::Find),
+    (&[Key::Alt('f')], Command::FindRegex),
+    (&[Key::Ctrl('r')], Command::Replace),
+    (&[Key::Ctrl('z')], Command::Undo),
+    (&[Key::Ctrl('y')], Command::Redo),
+    (&[Key::Alt(',')], Command::HistoryBack),
+    (&[Key::Alt('.')], Command::HistoryForward),
+    (&[Key::Null], Command::ToggleMark),
+    (&[Key::Esc], Command::ClearMark),
+    (&[Key::Ctrl('c')], Command::Copy),
+    (&[Key::Ctrl('x')], Command::Cut),
+    (&[Key::Ctrl('v')], Command::Paste),
+    (&[Key::Delete], Command::Delete),
+    (&[Key::Backspace], Command::Backspace),
+    (&[Key::Up], Command::MoveUp),
+    (&[Key::Down], Command::MoveDown),
+    (&[Key::Left], Command::MoveLeft),
+    (&[Key::Right], Command::MoveRight),
+    (&[Key::PageUp], Command::PageUp),
+    (&[Key::PageDown], Command::PageDown),
+    (&[Key::Home], Command::LineStart),
+    (&[Key::End], Command::LineEnd),
+    (&[Key::ShiftUp], Command::SelectUp),
+    (&[Key::ShiftDown], Command::SelectDown),
+    (&[Key::ShiftLeft], Command::SelectLeft),
+    (&[Key::ShiftRight], Command::SelectRight),
+    (&[Key::Ctrl('o')], Command::OpenFile),
+    (&[Key::Alt('n')], Command::NextBuffer),
+    (&[Key::Alt('p')], Command::PreviousBuffer),
+    (&[Key::Alt('b')], Command::ListBuffers),
+    (&[Key::Ctrl('w')], Command::CloseBuffer),
+    (&[Key::Alt('s')], Command::SplitHorizontal),
+    (&[Key::Alt('v')], Command::SplitVertical),
+    (&[Key::Alt('o')], Command::NextPane),
+    (&[Key::Alt('i')], Command::PreviousPane),
+    (&[Key::Alt('=')], Command::GrowPane),
+    (&[Key::Alt('-')], Command::ShrinkPane),
+    (&[Key::Alt('q')], Command::ClosePane),
+    (&[Key::Alt('l')], Command::ToggleLineNumbers),
+    (&[Key::Alt('z')], Command::ToggleWrap),
+    (&[Key::Alt('e')], Command::ConvertLineEndings),
+    (&[Key::Alt('E')], Command::SaveAsEncoding),
+];
+
+// The `emacs` preset. These replace any default binding they collide with,
+// e.g. Ctrl-X becomes a prefix instead of cut.
+const EMACS_BINDINGS: &[(&[Key], Command)] = &[
+    (&[Key::Ctrl('a')], Command::LineStart),
+    (&[Key::Ctrl('e')], Command::LineEnd),
+    (&[Key::Ctrl('f')], Command::MoveRight),
+    (&[Key::Ctrl('b')], Command::MoveLeft),
+    (&[Key::Ctrl('n')], Command::MoveDown),
+    (&[Key::Ctrl('p')], Command::MoveUp),
+    (&[Key::Ctrl('v')], Command::PageDown),
+    (&[Key::Alt('v')], Command::PageUp),
+    (&[Key::Ctrl('d')], Command::Delete),
+    (&[Key::Ctrl('k')], Command::KillLine),
+    (&[Key::Ctrl('w')], Command::Cut),
+    (&[Key::Alt('w')], Command::Copy),
+    (&[Key::Ctrl('y')], Command::Paste),
+    (&[Key::Alt('y')], Command::YankPop),
+    (&[Key::Ctrl('g')], Command::ClearMark),
+    (&[Key::Ctrl('s')], Command::Find),
+    (&[Key::Alt('%')], Command::Replace),
+    (&[Key::Ctrl('x'), Key::Ctrl('s')], Command::Save),
+    (&[Key::Ctrl('x'), Key::Ctrl('c')], Command::Quit),
+    (&[Key::Ctrl('x'), Key::Char('u')], Command::Undo),
+    (&[Key::Ctrl('x'), Key::Ctrl('f')], Command::OpenFile),
+    (&[Key::Ctrl('x'), Key::Right], Command::NextBuffer),
+    (&[Key::Ctrl('x'), Key::Left], Command::PreviousBuffer),
+    (&[Key::Ctrl('x'), Key::Ctrl('b')], Command::ListBuffers),
+    (&[Key::Ctrl('x'), Key::Char('k')], Command::CloseBuffer),
+    (&[Key::Ctrl('x'), Key::Char('2')], Command::SplitHorizontal),
+    (&[Key::Ctrl('x'), Key::Char('3')], Command::SplitVertical),
+    (&[Key::Ctrl('x'), Key::Char('o')], Command::NextPane),
+    (&[Key::Ctrl('x'), Key::Char('^')], Command::GrowPane),
+    (&[Key::Ctrl('x'), Key::Char('-')], Command::ShrinkPane),
+    (&[Key::Ctrl('x'), Key::Char('0')], Command::ClosePane),
+];
+
+impl Command {
+    pub fn from_name(name: &str) -> Option<Self> {
+        COMMANDS
+            .iter()
+            .find(|(command_name, _)| *command_name == name)
+            .map(|(_, command)| *command)
+    }
+
+    pub fn name(self) -> &'static str {
+        COMMANDS
+            .iter()
+            .find(|(_, command)| *command == self)
+            .map_or("", |(name, _)| name)
+    }
+}
+
+// What a sequence of keys pressed so far means.
+pub enum Lookup {
+    Command(Command),
+    // The start of a longer binding such as Ctrl-X Ctrl-S; wait for more keys.
+    Prefix,
+    Unbound,
+}
+
+pub struct Keymap {
+    bindings: HashMap<Vec<Key>, Command>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self {
+            bindings: DEFAULT_BINDINGS
+                .iter()
+                .map(|(keys, command)| (keys.to_vec(), *command))
+                .collect(),
+        }
+    }
+}
+
+impl Keymap {
+    // The default bindings, changed by the preset and then by the `[keys]`
+    // section of the config. Binding a key to "none" removes it. Unknown keys
+    // or commands and bindings that hide each other are returned as messages.
+    pub fn load(preset: &str, overrides: &BTreeMap<String, String>) -> (Self, Vec<String>) {
+        let mut keymap = Self::default();
+        if preset == "emacs" {
+            for (keys, command) in EMACS_BINDINGS {
+                keymap
+                    .bindings
+                    .retain(|bound, _| !bound.starts_with(keys) && !keys.starts_with(bound));
+                keymap.bindings.insert(keys.to_vec(), *command);
+            }
+        }
+        let mut errors = Vec::new();
+        let mut configured: Vec<Vec<Key>> = Vec::new();
+        for (sequence, name) in overrides {
+            let keys = match parse_keys(sequence) {
+                Ok(keys) => keys,
+                Err(error) => {
+                    errors.push(format!("keys: {error}"));
+                    continue;
+                }
+            };
+            let command = if name == "none" {
+                None
+            } else if let Some(command) = Command::from_name(name) {
+                Some(command)
+            } else {
+                errors.push(format!("keys: unknown command `{name}` for `{sequence}`"));
+                continue;
+            };
+            // "Ctrl-S" and "ctrl-s" are the same key.
+            if configured.contains(&keys) {
+                errors.push(format!("keys: `{}` is bound more than once", keys_name(&keys)));
+                continue;
+            }
+            match command {
+                Some(command) => keymap.bindings.insert(keys.clone(), command),
+                None => keymap.bindings.remove(&keys),
+            };
+            configured.push(keys);
+        }
+
+        // A binding that is the start of another one would make the longer
+        // one unreachable. Configured bindings win over defaults, and between
+        // two configured ones the shorter is the one that can be reached.
+        let mut sequences: Vec<Vec<Key>> = keymap.bindings.keys().cloned().collect();
+        sequences.sort_by_key(Vec::len);
+        for (index, short) in sequences.iter().enumerate() {
+            for long in sequences.iter().skip(index.saturating_add(1)) {
+                if long.len() == short.len() || !long.starts_with(short) {
+                    continue;
+                }
+                let (Some(short_command), Some(long_command)) =
+                    (keymap.bindings.get(short), keymap.bindings.get(long))
+                else {
+                    continue;
+                };
+                errors.push(format!(
+                    "keys: `{}` ({}) conflicts with `{}` ({})",
+                    keys_name(long),
+                    long_command.name(),
+                    keys_name(short),
+                    short_command.name()
+                ));
+                if configured.contains(short) || !configured.contains(long) {
+                    keymap.bindings.remove(long);
+                } else {
+                    keymap.bindings.remove(short);
+                }
+            }
+        }
+        (keymap, errors)
+    }
+
+    pub fn lookup(&self, keys: &[Key]) -> Lookup {
+        if let Some(command) = self.bindings.get(keys) {
+            return Lookup::Command(*command);
+        }
+        if self.bindings.keys().any(|sequence| sequence.starts_with(keys)) {
+            Lookup::Prefix
+        } else {
+            Lookup::Unbound
+        }
+    }
+
+    // The shortest key sequence that runs `command`, for help texts.
+    pub fn describe(&self, command: Command) -> Option<String> {
+        self.bindings
+            .iter()
+            .filter(|(_, bound)| **bound == command)
+            .map(|(keys, _)| keys_name(keys))
+            .min_by(|a, b| a.len().cmp(&b.len()).then_with(|| a.cmp(b)))
+    }
+}
+
+// Parses a space separated key sequence such as "ctrl-x ctrl-s".
+pub fn parse_keys(sequence: &str) -> Result<Vec<Key>, String> {
+    let keys = sequence
+        .split_whitespace()
+        .map(parse_key)
+        .collect::<Option<Vec<Key>>>()
+        .ok_or_else(|| format!("unknown key `{sequence}`"))?;
+    if keys.is_empty() {
+        return Err(String::from("empty key sequence"));
+    }
+    Ok(keys)
+}
+
+fn parse_key(name: &str) -> Option<Key> {
+    let lower = name.to_lowercase();
+    // A lone "-" is a key too, so only strip a modifier if something follows.
+    let (modifier, base) = ["ctrl-", "alt-", "meta-", "shift-"]
+        .iter()
+        .find(|prefix| lower.starts_with(*prefix) && lower.len() > prefix.len())
+        .map_or(("", name), |prefix| (*prefix, &name[prefix.len()..]));
+    let lower_base = base.to_lowercase();
+    let mut chars = base.chars();
+    let single = match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    };
+    let key = match (modifier, lower_base.as_str(), single) {
+        ("ctrl-", "space" | "@", _) => Key::Null,
+        ("ctrl-", "up", _) => Key::CtrlUp,
+        ("ctrl-", "down", _) => Key::CtrlDown,
+        ("ctrl-", "left", _) => Key::CtrlLeft,
+        ("ctrl-", "right", _) => Key::CtrlRight,
+        ("ctrl-", "home", _) => Key::CtrlHome,
+        ("ctrl-", "end", _) => Key::CtrlEnd,
+        ("ctrl-", _, Some(c)) if c.is_ascii_graphic() => Key::Ctrl(c.to_ascii_lowercase()),
+        ("alt-" | "meta-", "up", _) => Key::AltUp,
+        ("alt-" | "meta-", "down", _) => Key::AltDown,
+        ("alt-" | "meta-", "left", _) => Key::AltLeft,
+        ("alt-" | "meta-", "right", _) => Key::AltRight,
+        ("alt-" | "meta-", "space", _) => Key::Alt(' '),
+        // ESC O and ESC [ start the sequences other keys send, so these
+        // never arrive as keys of their own.
+        ("alt-" | "meta-", _, Some('O' | '[')) => return None,
+        ("alt-" | "meta-", _, Some(c)) => Key::Alt(c),
+        ("shift-", "up", _) => Key::ShiftUp,
+        ("shift-", "down", _) => Key::ShiftDown,
+        ("shift-", "left", _) => Key::ShiftLeft,
+        ("shift-", "right", _) => Key::ShiftRight,
+        ("shift-", "tab", _) => Key::BackTab,
+        ("", _, Some(c)) => Key::Char(c),
+        ("", "up", _) => Key::Up,
+        ("", "down", _) => Key::Down,
+        ("", "left", _) => Key::Left,
+        ("", "right", _) => Key::Right,
+        ("", "home", _) => Key::Home,
+        ("", "end", _) => Key::End,
+        ("", "pageup", _) => Key::PageUp,
+        ("", "pagedown", _) => Key::PageDown,
+        ("", "delete" | "del", _) => Key::Delete,
+        ("", "insert", _) => Key::Insert,
+        ("", "backspace", _) => Key::Backspace,
+        ("", "esc" | "escape", _) => Key::Esc,
+        ("", "enter" | "return", _) => Key::Char('\n'),
+        ("", "tab", _) => Key::Char('\t'),
+        ("", "space", _) => Key::Char(' '),
+        ("", function, _) => {
+            let number = function.strip_prefix('f')?.parse::<u8>().ok()?;
+            if !(1..=12).contains(&number) {
+                return None;
+            }
+            Key::F(number)
+        }
+        _ => return None,
+    };
+    Some(key)
+}
+
+pub fn keys_name(keys: &[Key]) -> String {
+    keys.iter().map(|key| key_name(*key)).collect::<Vec<String>>().join(" ")
+}
+
+pub fn key_name(key: Key) -> String {
+    match key {
+        Key::Ctrl(c) => format!("Ctrl-{}", c.to_ascii_uppercase()),
+        Key::Alt(' ') => String::from("Alt-Space"),
+        Key::Alt(c) => format!("Alt-{c}"),
+        Key::Char('\n') => String::from("Enter"),
+        Key::Char('\t') => String::from("Tab"),
+        Key::Char(' ') => String::from("Space"),
+        Key::Char(c) => c.to_string(),
+        Key::F(number) => format!("F{number}"),
+        Key::Null => String::from("Ctrl-Space"),
+        Key::Esc => String::from("Esc"),
+        Key::Backspace => String::from("Backspace"),
+        Key::Delete => String::from("Delete"),
+        Key::Insert => String::from("Insert"),
+        Key::Up => String::from("Up"),
+        Key::Down => String::from("Down"),
+        Key::Left => String::from("Left"),
+        Key::Right => String::from("Right"),
+        Key::ShiftUp => String::from("Shift-Up"),
+        Key::ShiftDown => String::from("Shift-Down"),
+        Key::ShiftLeft => String::from("Shift-Left"),
+        Key::ShiftRight => String::from("Shift-Right"),
+        Key::AltUp => String::from("Alt-Up"),
+        Key::AltDown => String::from("Alt-Down"),
+        Key::AltLeft => String::from("Alt-Left"),
+        Key::AltRight => String::from("Alt-Right"),
+        Key::CtrlUp => String::from("Ctrl-Up"),
+        Key::CtrlDown => String::from("Ctrl-Down"),
+        Key::CtrlLeft => String::from("Ctrl-Left"),
+        Key::CtrlRight => String::from("Ctrl-Right"),
+        Key::Home => String::from("Home"),
+        Key::End => String::from("End"),
+        Key::CtrlHome => String::from("Ctrl-Home"),
+        Key::CtrlEnd => String::from("Ctrl-End"),
+        Key::PageUp => String::from("PageUp"),
+        Key::PageDown => String::from("PageDown"),
+        Key::BackTab => String::from("Shift-Tab"),
+        _ => String::from("?"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(preset: &str, overrides: &[(&str, &str)]) -> (Keymap, Vec<String>) {
+        let overrides = overrides
+            .iter()
+            .map(|(keys, command)| ((*keys).to_string(), (*command).to_string()))
+            .collect();
+        Keymap::load(preset, &overrides)
+    }
+
+    fn command(keymap: &Keymap, keys: &[Key]) -> Option<Command> {
+        match keymap.lookup(keys) {
+            Lookup::Command(command) => Some(command),
+            Lookup::Prefix | Lookup::Unbound => None,
+        }
+    }
+
+    #[test]
+    fn parses_modifiers_and_named_keys() {
+        assert_eq!(parse_keys("Ctrl-X ctrl-s"), Ok(vec![Key::Ctrl('x'), Key::Ctrl('s')]));
+        assert_eq!(parse_keys("alt-E"), Ok(vec![Key::Alt('E')]));
+        assert_eq!(parse_keys("meta-space"), Ok(vec![Key::Alt(' ')]));
+        assert_eq!(parse_keys("ctrl-space"), Ok(vec![Key::Null]));
+        assert_eq!(parse_keys("shift-up PageDown"), Ok(vec![Key::ShiftUp, Key::PageDown]));
+        assert_eq!(parse_keys("- f12 enter"), Ok(vec![Key::Char('-'), Key::F(12), Key::Char('\n')]));
+    }
+
+    #[test]
+    fn rejects_unknown_and_unreachable_keys() {
+        assert!(parse_keys("").is_err());
+        assert!(parse_keys("ctrl-x hyper-a").is_err());
+        assert!(parse_keys("f13").is_err());
+        assert!(parse_keys("alt-O").is_err());
+        assert!(parse_keys("alt-[").is_err());
+    }
+
+    #[test]
+    fn key_names_parse_back() {
+        for (keys, _) in DEFAULT_BINDINGS.iter().chain(EMACS_BINDINGS) {
+            let name = keys_name(keys);
+            assert_eq!(parse_keys(&name).as_deref(), Ok(*keys), "{name}");
+        }
+    }
+
+    #[test]
+    fn every_command_has_a_name() {
+        for (name, command) in COMMANDS {
+            assert_eq!(Command::from_name(name), Some(*command));
+            assert_eq!(command.name(), *name);
+        }
+    }
+
+    #[test]
+    fn defaults_have_no_conflicts() {
+        assert!(load("standard", &[]).1.is_empty());
+        assert!(load("emacs", &[]).1.is_empty());
+    }
+
+    #[test]
+    fn overrides_replace_and_remove_bindings() {
+        let (keymap, errors) = load("standard", &[("ctrl-s", "quit"), ("ctrl-q", "none")]);
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(command(&keymap, &[Key::Ctrl('s')]), Some(Command::Quit));
+        assert_eq!(command(&keymap, &[Key::Ctrl('q')]), None);
+    }
+
+    #[test]
+    fn emacs_prefix_waits_for_more_keys() {
+        let (keymap, _) = load("emacs", &[]);
+        assert!(matches!(keymap.lookup(&[Key::Ctrl('x')]), Lookup::Prefix));
+        assert_eq!(command(&keymap, &[Key::Ctrl('x'), Key::Ctrl('s')]), Some(Command::Save));
+    }
+
+    #[test]
+    fn configured_prefix_hides_default_sequence() {
+        // Binding Ctrl-X alone makes the emacs Ctrl-X sequences unreachable.
+        let (keymap, errors) = load("emacs", &[("ctrl-x", "cut")]);
+        assert!(!errors.is_empty());
+        assert!(errors.iter().all(|error| error.contains("conflicts with `Ctrl-X` (cut)")));
+        assert_eq!(command(&keymap, &[Key::Ctrl('x')]), Some(Command::Cut));
+    }
+
+    #[test]
+    fn configured_sequence_beats_default_prefix() {
+        let (keymap, errors) = load("standard", &[("ctrl-s ctrl-s", "save")]);
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(keymap.lookup(&[Key::Ctrl('s')]), Lookup::Prefix));
+        assert_eq!(command(&keymap, &[Key::Ctrl('s'), Key::Ctrl('s')]), Some(Command::Save));
+    }
+
+    #[test]
+    fn between_configured_bindings_the_shorter_wins() {
+        let (keymap, errors) = load("standard", &[("ctrl-t", "undo"), ("ctrl-t ctrl-t", "redo")]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(command(&keymap, &[Key::Ctrl('t')]), Some(Command::Undo));
+        assert_eq!(command(&keymap, &[Key::Ctrl('t'), Key::Ctrl('t')]), None);
+    }
+
+    #[test]
+    fn repeated_and_unknown_bindings_are_reported() {
+        let (_, errors) = load("standard", &[("ctrl-t", "undo"), ("Ctrl-T", "redo"), ("ctrl-u", "fly")]);
+        assert_eq!(errors.len(), 2, "{errors:?}");
+        assert!(errors.iter().any(|error| error.contains("bound more than once")));
+        assert!(errors.iter().any(|error| error.contains("unknown command `fly`")));
+    }
+}
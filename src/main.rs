@@ -9,16 +9,35 @@ clippy::else_if_without_else,
 clippy::unnecessary_cast
 )]
 
+mod buffer;
+mod clipboard;
+mod config;
 mod document;
 mod editor;
+mod encoding;
+mod filetype;
+mod highlighting;
+mod history;
+mod keymap;
 mod row;
+mod swap;
 mod terminal;
+mod theme;
+mod vim;
+mod window;
 
+pub use buffer::Buffer;
+pub use config::Config;
 pub use document::Document;
 use editor::Editor;
 pub use editor::Position;
+pub use editor::SearchDirection;
+pub use filetype::FileType;
+pub use filetype::FileTypes;
+pub use filetype::HighlightingOptions;
 pub use row::Row;
 pub use terminal::Terminal;
+pub use theme::Theme;
 
 fn main() {
 
@@ -1,9 +1,26 @@
+use crate::highlighting::{self, Open};
+use crate::Config;
+use crate::HighlightingOptions;
+use crate::SearchDirection;
+use crate::Theme;
+use regex::Regex;
 use std::cmp;
+use std::ops::Range;
+use termion::style;
 use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 #[derive(Default)]
 pub struct Row {
     string: String,
+    // Syntax highlighting per grapheme, valid while `is_highlighted` is set
+    // and the row above still ends with `open_at_start`.
+    highlighting: Vec<highlighting::Type>,
+    is_highlighted: bool,
+    open_at_start: Open,
+    open_at_end: Open,
+    // Search matches, drawn on top of the syntax highlighting.
+    matches: Vec<Range<usize>>,
     len: usize,
 }
 
@@ -11,6 +28,11 @@ impl From<&str> for Row {
     fn from(slice: &str) -> Self {
         let mut row = Self {
             string: String::from(slice),
+            highlighting: Vec::new(),
+            is_highlighted: false,
+            open_at_start: Open::None,
+            open_at_end: Open::None,
+            matches: Vec::new(),
             len: 0,
         };
         row.update_len();
@@ -19,32 +41,154 @@ impl From<&str> for Row {
 }
 
 impl Row {
-    pub fn render(&self, start: usize, end: usize) -> String {
-        let end = cmp::min(end, self.string.len());
-        let start = cmp::min(start, end);
+    // Draws the columns from `start` up to `end`. `selection` is the range of
+    // graphemes to draw in reverse video.
+    pub fn render(&self, start: usize, end: usize, selection: Option<Range<usize>>, theme: &Theme, config: &Config) -> String {
         let mut result = String::new();
-        for grapheme in self.string[..]
-            .graphemes(true)
-            .skip(start)
-            .take(end - start)
-        {
-            // if we encounter a tab, we use spaces on a file we display.
-            if grapheme == "\t" {
-                result.push_str(" ");
+        let mut current_highlighting = &highlighting::Type::None;
+        let mut inverted = false;
+        let selection_color = theme.selection_color();
+        let mut column = 0;
+        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
+            let width = grapheme_width(grapheme, column, config.tab_width);
+            let grapheme_start = column;
+            column = column.saturating_add(width);
+            if grapheme_start < start && column <= start {
+                continue;
+            }
+            if grapheme_start >= end {
+                break;
+            }
+            // Only emit a color escape when the highlighting changes, not for every grapheme.
+            let is_match = self.matches.iter().any(|range| range.contains(&index));
+            let highlighting_type = if is_match {
+                &highlighting::Type::Match
+            } else {
+                self.highlighting
+                    .get(index)
+                    .unwrap_or(&highlighting::Type::None)
+            };
+            if highlighting_type != current_highlighting {
+                current_highlighting = highlighting_type;
+                result.push_str(&theme.highlight_color(*highlighting_type));
+            }
+            // Without colors, search matches are shown in reverse video too.
+            let selected = selection.as_ref().is_some_and(|range| range.contains(&index))
+                || (is_match && !theme.has_colors());
+            if selected != inverted {
+                inverted = selected;
+                match (selected, &selection_color) {
+                    (true, Some(color)) => result.push_str(color),
+                    (true, None) => result.push_str(&format!("{}", style::Invert)),
+                    (false, Some(_)) => result.push_str(&theme.reset_bg()),
+                    (false, None) => result.push_str(&format!("{}", style::NoInvert)),
+                }
+            }
+            // if we encounter a tab, we use spaces on a file we display. So
+            // does a wide character cut in half by either edge.
+            if grapheme == "\t" || grapheme_start < start || column > end {
+                let visible = cmp::min(column, end).saturating_sub(cmp::max(grapheme_start, start));
+                result.push_str(&" ".repeat(visible));
             } else {
                 result.push_str(grapheme);
             }
         }
+        if *current_highlighting != highlighting::Type::None {
+            result.push_str(&theme.reset_fg());
+        }
+        if inverted {
+            if selection_color.is_some() {
+                result.push_str(&theme.reset_bg());
+            } else {
+                result.push_str(&format!("{}", style::NoInvert));
+            }
+        }
         result
     }
+
+    // The columns each grapheme takes up on screen.
+    fn widths(&self, tab_width: usize) -> Vec<usize> {
+        let mut column: usize = 0;
+        self.string[..]
+            .graphemes(true)
+            .map(|grapheme| {
+                let width = grapheme_width(grapheme, column, tab_width);
+                column = column.saturating_add(width);
+                width
+            })
+            .collect()
+    }
+
+    // The screen column grapheme `x` starts at.
+    pub fn column(&self, x: usize, tab_width: usize) -> usize {
+        self.widths(tab_width).iter().take(x).sum()
+    }
+
+    // The grapheme that covers screen column `column`, or the end of the row
+    // when the row is shorter than that.
+    pub fn index_at(&self, column: usize, tab_width: usize) -> usize {
+        let mut end: usize = 0;
+        for (index, width) in self.widths(tab_width).into_iter().enumerate() {
+            end = end.saturating_add(width);
+            if end > column {
+                return index;
+            }
+        }
+        self.len
+    }
+
+    // Where each screen line starts when the row is wrapped at `width`
+    // columns, and at `continued` columns on the lines after the first. A
+    // line never takes up its last column when it ends the row, to leave
+    // room for the cursor behind it.
+    #[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
+    pub fn wrap(&self, width: usize, continued: usize, at_words: bool, tab_width: usize) -> Vec<usize> {
+        let graphemes: Vec<&str> = self.string[..].graphemes(true).collect();
+        let widths = self.widths(tab_width);
+        let mut starts = vec![0];
+        let mut start = 0;
+        let mut available = cmp::max(width, 1);
+        // Columns taken on the current line so far.
+        let mut used = 0;
+        // Where the current line could break after a space.
+        let mut after_space = None;
+        for (index, width) in widths.iter().enumerate() {
+            while used + width > available && index > start {
+                // Break after the last space that fits, unless that would
+                // leave the line empty.
+                let end = match after_space {
+                    Some(end) if at_words && end > start => end,
+                    _ => index,
+                };
+                used = widths[end..index].iter().sum();
+                starts.push(end);
+                start = end;
+                after_space = None;
+                available = cmp::max(continued, 1);
+            }
+            used += width;
+            if graphemes[index].chars().all(char::is_whitespace) {
+                after_space = Some(index + 1);
+            }
+        }
+        if used >= available {
+            starts.push(self.len);
+        }
+        starts
+    }
+
     pub fn len(&self) -> usize {
         self.len
     }
     pub fn is_empty(&self) -> bool {
         self.len == 0
     }
+    // Called after every change to the text, so it also invalidates the
+    // cached highlighting.
     fn update_len(&mut self) {
         self.len = self.string[..].graphemes(true).count();
+        self.is_highlighted = false;
+        self.matches.clear();
     }
 
     pub fn insert(&mut self, at: usize, c: char) {
@@ -75,6 +219,33 @@ impl Row {
         }
         self.update_len()
     }
+    // The graphemes from `start` up to (not including) `end`.
+    #[allow(clippy::integer_arithmetic)]
+    pub fn substring(&self, start: usize, end: usize) -> String {
+        self.string[..]
+            .graphemes(true)
+            .skip(start)
+            .take(end.saturating_sub(start))
+            .collect()
+    }
+
+    // Inserts a whole string at grapheme index `at` in one go.
+    pub fn insert_str(&mut self, at: usize, string: &str) {
+        let byte_index = self.byte_index(at);
+        self.string.insert_str(byte_index, string);
+        self.update_len();
+    }
+
+    // Removes `len` graphemes starting at `at` and returns them.
+    #[allow(clippy::integer_arithmetic)]
+    pub fn delete_range(&mut self, at: usize, len: usize) -> String {
+        let start = self.byte_index(at);
+        let end = self.byte_index(at + len);
+        let removed: String = self.string.drain(start..end).collect();
+        self.update_len();
+        removed
+    }
+
     pub fn split(&mut self, at: usize) -> Self {
         let beginning: String = self.string[..].graphemes(true).take(at).collect();
         let remainder: String = self.string[..].graphemes(true).skip(at).collect();
@@ -86,4 +257,591 @@ impl Row {
     pub fn as_bytes(&self) -> &[u8]{
         self.string.as_bytes()
     }
-}
\ No newline at end of file
+
+    // Returns the grapheme index of the first match of `query`, searching
+    // forward from `at` or backward from just before `at`.
+    #[allow(clippy::integer_arithmetic)]
+    pub fn find(&self, query: &str, at: usize, direction: SearchDirection) -> Option<usize> {
+        if at > self.len || query.is_empty() {
+            return None;
+        }
+        let start = if direction == SearchDirection::Forward {
+            at
+        } else {
+            0
+        };
+        let end = if direction == SearchDirection::Forward {
+            self.len
+        } else {
+            at
+        };
+        let substring: String = self.string[..]
+            .graphemes(true)
+            .skip(start)
+            .take(end - start)
+            .collect();
+        let matching_byte_index = if direction == SearchDirection::Forward {
+            substring.find(query)
+        } else {
+            substring.rfind(query)
+        };
+        if let Some(matching_byte_index) = matching_byte_index {
+            // `find` works on bytes, but the cursor works on graphemes.
+            for (grapheme_index, (byte_index, _)) in substring[..].grapheme_indices(true).enumerate() {
+                if matching_byte_index == byte_index {
+                    return Some(start + grapheme_index);
+                }
+            }
+        }
+        None
+    }
+
+    // Marks every occurrence of `word` in the row, or clears the marks when
+    // there is no word.
+    pub fn highlight(&mut self, word: Option<&str>) {
+        self.matches.clear();
+        if let Some(word) = word {
+            let word_len = word[..].graphemes(true).count();
+            let mut index = 0;
+            while let Some(search_match) = self.find(word, index, SearchDirection::Forward) {
+                if let Some(next_index) = search_match.checked_add(word_len) {
+                    self.matches.push(search_match..next_index);
+                    index = next_index;
+                } else {
+                    break;
+                }
+            }
+        }
+    }
+
+    // Same as `find`, but for a regular expression. Returns the grapheme index
+    // of the match and its length in graphemes.
+    #[allow(clippy::integer_arithmetic)]
+    pub fn find_regex(&self, regex: &Regex, at: usize, direction: SearchDirection) -> Option<(usize, usize)> {
+        if at > self.len {
+            return None;
+        }
+        let byte_at = self.byte_index(at);
+        let found = if direction == SearchDirection::Forward {
+            regex.find_at(&self.string, byte_at)
+        } else {
+            regex
+                .find_iter(&self.string)
+                .take_while(|found| found.start() < byte_at)
+                .last()
+        };
+        found.map(|found| {
+            let start = self.grapheme_index(found.start());
+            (start, self.grapheme_index(found.end()) - start)
+        })
+    }
+
+    pub fn highlight_regex(&mut self, regex: Option<&Regex>) {
+        self.matches.clear();
+        if let Some(regex) = regex {
+            for found in regex.find_iter(&self.string) {
+                let start = self.grapheme_index(found.start());
+                let end = self.grapheme_index(found.end());
+                self.matches.push(start..end);
+            }
+        }
+    }
+
+    // Highlights a single match, e.g. the one waiting for a replace confirmation.
+    #[allow(clippy::integer_arithmetic)]
+    pub fn highlight_match(&mut self, at: usize, len: usize) {
+        self.matches.clear();
+        self.matches.push(at..at + len);
+    }
+
+    pub fn open_at_end(&self) -> Open {
+        self.open_at_end.clone()
+    }
+
+    // Forces the next `highlight_syntax` to start from scratch.
+    pub fn unhighlight(&mut self) {
+        self.is_highlighted = false;
+    }
+
+    // Works out the syntax highlighting for the row, given the construct
+    // left open by the row above, and returns the one left open by this row.
+    // The cached result is reused while the row and `open` stay the same.
+    #[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
+    pub fn highlight_syntax(&mut self, opts: &HighlightingOptions, open: &Open) -> Open {
+        if self.is_highlighted && self.open_at_start == *open {
+            return self.open_at_end.clone();
+        }
+        self.open_at_start = open.clone();
+        self.is_highlighted = true;
+        let graphemes: Vec<(usize, &str)> = self.string[..].grapheme_indices(true).collect();
+        let mut highlighting = vec![highlighting::Type::None; graphemes.len()];
+
+        let mut index = 0;
+        let mut open = open.clone();
+        // Finish whatever the previous row left open.
+        let (close, escapes, hl_type) = match &open {
+            Open::None => (None, false, highlighting::Type::None),
+            Open::Comment => (opts.multiline_comment().map(|(_, close)| close.to_string()), false, highlighting::Type::Comment),
+            Open::String { close, escapes } => (Some(close.clone()), *escapes, highlighting::Type::String),
+        };
+        if let Some(close) = close {
+            let end = self.find_closing(&graphemes, 0, &close, escapes);
+            let end_index = end.unwrap_or(graphemes.len());
+            for hl in &mut highlighting[..end_index] {
+                *hl = hl_type;
+            }
+            if end.is_none() {
+                self.highlighting = highlighting;
+                self.open_at_end = open.clone();
+                return open;
+            }
+            index = end_index;
+            open = Open::None;
+        } else if opts.is_heading(&self.string) {
+            self.highlighting = vec![highlighting::Type::Heading; graphemes.len()];
+            self.open_at_end = Open::None;
+            return Open::None;
+        }
+
+        while let Some(&(byte_index, grapheme)) = graphemes.get(index) {
+            let previous_is_separator = index == 0 || is_separator(graphemes[index - 1].1);
+            let rest = &self.string[byte_index..];
+
+            // Constructs that may span rows: block comments, multi-line and
+            // raw strings.
+            let mut opened = None;
+            if let Some((start, close)) = opts.multiline_comment() {
+                if rest.starts_with(start) {
+                    opened = Some((start.graphemes(true).count(), close.to_string(), false, Open::Comment, highlighting::Type::Comment));
+                }
+            }
+            if opened.is_none() && opts.raw_strings() && previous_is_separator {
+                // r"...", r#"..."#, r##"..."## and so on.
+                if let Some(after_r) = rest.strip_prefix('r') {
+                    let hashes = after_r.len() - after_r.trim_start_matches('#').len();
+                    if after_r[hashes..].starts_with('"') {
+                        let close = format!("\"{}", "#".repeat(hashes));
+                        let open = Open::String {
+                            close: close.clone(),
+                            escapes: false,
+                        };
+                        opened = Some((hashes + 2, close, false, open, highlighting::Type::String));
+                    }
+                }
+            }
+            if opened.is_none() {
+                if let Some((start, close)) = opts.multiline_strings().iter().find(|(start, _)| rest.starts_with(start.as_str())) {
+                    let open = Open::String {
+                        close: close.clone(),
+                        escapes: true,
+                    };
+                    opened = Some((start.graphemes(true).count(), close.clone(), true, open, highlighting::Type::String));
+                }
+            }
+            if let Some((start_len, close, escapes, construct, hl_type)) = opened {
+                let end = self.find_closing(&graphemes, index + start_len, &close, escapes);
+                let end_index = end.unwrap_or(graphemes.len());
+                for hl in &mut highlighting[index..end_index] {
+                    *hl = hl_type;
+                }
+                if end.is_none() {
+                    open = construct;
+                    break;
+                }
+                index = end_index;
+                continue;
+            }
+
+            // Line comment: everything up to the end of the row.
+            if let Some(comment) = opts.comment() {
+                if self.string[byte_index..].starts_with(comment) {
+                    for hl in highlighting.iter_mut().skip(index) {
+                        *hl = highlighting::Type::Comment;
+                    }
+                    break;
+                }
+            }
+
+            // String: up to the closing delimiter, skipping escaped ones.
+            if let Some(delimiter) = opts.strings().iter().find(|c| grapheme.starts_with(**c)) {
+                let mut end = index + 1;
+                while let Some(&(_, next)) = graphemes.get(end) {
+                    end += 1;
+                    if next == "\\" {
+                        end += 1;
+                    } else if next.starts_with(*delimiter) {
+                        break;
+                    }
+                }
+                let end = cmp::min(end, graphemes.len());
+                for hl in &mut highlighting[index..end] {
+                    *hl = highlighting::Type::String;
+                }
+                index = end;
+                continue;
+            }
+
+            // Character literal: 'a' or an escape like '\n'. A lone quote, as
+            // in a Rust lifetime, is left alone. The longest escape is
+            // '\u{10FFFF}', so the closing quote is at most 11 graphemes on.
+            if opts.characters() && grapheme == "'" {
+                let closing = if graphemes.get(index + 1).map(|g| g.1) == Some("\\") {
+                    (index + 3..cmp::min(index + 12, graphemes.len())).find(|&i| graphemes[i].1 == "'")
+                } else if graphemes.get(index + 2).map(|g| g.1) == Some("'") {
+                    Some(index + 2)
+                } else {
+                    None
+                };
+                if let Some(closing) = closing {
+                    for hl in &mut highlighting[index..=closing] {
+                        *hl = highlighting::Type::Character;
+                    }
+                    index = closing + 1;
+                    continue;
+                }
+            }
+
+            // Words: numbers, keywords and types. Other identifiers are
+            // skipped whole, so a digit inside `x2` isn't a number.
+            if previous_is_separator && !is_separator(grapheme) {
+                let is_number = opts.numbers() && grapheme.starts_with(|c: char| c.is_ascii_digit());
+                let mut end = index + 1;
+                while let Some(&(_, next)) = graphemes.get(end) {
+                    if is_separator(next) && !(is_number && next == ".") {
+                        break;
+                    }
+                    end += 1;
+                }
+                let word_end = graphemes.get(end).map_or(self.string.len(), |g| g.0);
+                let word = &self.string[byte_index..word_end];
+                let word_type = if is_number {
+                    highlighting::Type::Number
+                } else if opts.keywords().iter().any(|keyword| keyword == word) {
+                    highlighting::Type::Keyword
+                } else if opts.types().iter().any(|keyword| keyword == word) {
+                    highlighting::Type::Type
+                } else {
+                    highlighting::Type::None
+                };
+                for hl in &mut highlighting[index..end] {
+                    *hl = word_type;
+                }
+                index = end;
+                continue;
+            }
+            index += 1;
+        }
+        self.highlighting = highlighting;
+        self.open_at_end = open.clone();
+        open
+    }
+
+    // Index just past `close`, searching from `from`, or `None` if the row
+    // ends first. With `escapes`, a backslash hides the next grapheme.
+    #[allow(clippy::integer_arithmetic)]
+    fn find_closing(&self, graphemes: &[(usize, &str)], from: usize, close: &str, escapes: bool) -> Option<usize> {
+        let mut index = from;
+        while let Some(&(byte_index, grapheme)) = graphemes.get(index) {
+            if escapes && grapheme == "\\" {
+                index += 2;
+                continue;
+            }
+            if self.string[byte_index..].starts_with(close) {
+                return Some(index + close.graphemes(true).count());
+            }
+            index += 1;
+        }
+        None
+    }
+
+    // Replaces the match of `regex` starting at `at` with `replacement`,
+    // expanding capture group references such as `$1` or `${name}`.
+    // Returns the matched and the inserted text; the row is left untouched
+    // when they are the same.
+    pub fn replace_regex(&mut self, regex: &Regex, at: usize, replacement: &str) -> Option<(String, String)> {
+        let byte_at = self.byte_index(at);
+        let captures = regex.captures_at(&self.string, byte_at)?;
+        let whole = captures.get(0)?;
+        if whole.start() != byte_at {
+            return None;
+        }
+        let mut expanded = String::new();
+        captures.expand(replacement, &mut expanded);
+        let matched = whole.as_str().to_string();
+        if expanded != matched {
+            self.string.replace_range(whole.range(), &expanded);
+            self.update_len();
+        }
+        Some((matched, expanded))
+    }
+
+    // Byte offset where the grapheme at `at` starts.
+    fn byte_index(&self, at: usize) -> usize {
+        self.string[..]
+            .grapheme_indices(true)
+            .nth(at)
+            .map_or(self.string.len(), |(index, _)| index)
+    }
+
+    // Index of the grapheme starting at `byte_index`.
+    fn grapheme_index(&self, byte_index: usize) -> usize {
+        self.string[..]
+            .grapheme_indices(true)
+            .take_while(|(index, _)| *index < byte_index)
+            .count()
+    }
+}
+
+// Anything that can't be part of a word or number.
+fn is_separator(grapheme: &str) -> bool {
+    grapheme
+        .chars()
+        .all(|c| c.is_whitespace() || (c.is_ascii_punctuation() && c != '_'))
+}
+
+// The columns `text` takes up on screen, for one line text like messages.
+pub fn text_width(text: &str) -> usize {
+    text.graphemes(true)
+        .fold(0, |width, grapheme| width.saturating_add(grapheme_width(grapheme, width, 1)))
+}
+
+// The start of `text` that fits in `width` columns, without splitting a
+// grapheme.
+pub fn truncate_to_width(text: &str, width: usize) -> &str {
+    let mut column: usize = 0;
+    for (index, grapheme) in text.grapheme_indices(true) {
+        column = column.saturating_add(grapheme_width(grapheme, column, 1));
+        if column > width {
+            return &text[..index];
+        }
+    }
+    text
+}
+
+// Columns `grapheme` takes up when drawn at `column`. A tab reaches to the
+// next tab stop, and no grapheme is wider than two columns however many code
+// points it's made of; combining marks add nothing.
+fn grapheme_width(grapheme: &str, column: usize, tab_width: usize) -> usize {
+    if grapheme == "\t" {
+        let tab_width = cmp::max(tab_width, 1);
+        tab_width.saturating_sub(column % tab_width)
+    } else {
+        cmp::min(grapheme.width(), 2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::theme::ColorMode;
+    use crate::{FileType, FileTypes};
+    use std::{fs, process};
+
+    fn wrap(text: &str, width: usize, continued: usize, at_words: bool) -> Vec<usize> {
+        Row::from(text).wrap(width, continued, at_words, 4)
+    }
+
+    #[test]
+    fn short_rows_stay_on_one_line() {
+        assert_eq!(wrap("", 4, 4, false), vec![0]);
+        assert_eq!(wrap("abc", 4, 4, false), vec![0]);
+    }
+
+    #[test]
+    fn full_line_leaves_room_for_the_cursor() {
+        assert_eq!(wrap("abcd", 4, 4, false), vec![0, 4]);
+    }
+
+    #[test]
+    fn wraps_anywhere() {
+        assert_eq!(wrap("one two three", 6, 6, false), vec![0, 6, 12]);
+        assert_eq!(wrap("abcdefgh", 4, 2, false), vec![0, 4, 6, 8]);
+    }
+
+    #[test]
+    fn wraps_at_words() {
+        assert_eq!(wrap("one two three", 6, 6, true), vec![0, 4, 8]);
+        // A word longer than the line is broken anywhere.
+        assert_eq!(wrap("abcdefgh ij", 4, 4, true), vec![0, 4, 8]);
+    }
+
+    #[test]
+    fn wide_characters_move_to_the_next_line_whole() {
+        assert_eq!(wrap("日本語", 5, 5, false), vec![0, 2]);
+    }
+
+    #[test]
+    fn tabs_reach_the_next_stop() {
+        assert_eq!(wrap("\tx", 4, 4, false), vec![0, 1]);
+        assert_eq!(wrap("ab\tx", 4, 4, false), vec![0, 3]);
+    }
+
+    #[test]
+    fn columns_and_indices_agree() {
+        let row = Row::from("a日b\tc");
+        assert_eq!(row.column(2, 4), 3);
+        assert_eq!(row.column(4, 4), 8);
+        assert_eq!(row.column(4, 8), 8);
+        // Both columns of a wide character belong to it.
+        assert_eq!(row.index_at(1, 4), 1);
+        assert_eq!(row.index_at(2, 4), 1);
+        assert_eq!(row.index_at(5, 4), 3);
+        assert_eq!(row.index_at(20, 4), 5);
+    }
+
+    #[test]
+    fn combining_marks_take_no_column() {
+        let row = Row::from("e\u{301}x");
+        assert_eq!(row.len(), 2);
+        assert_eq!(row.column(1, 4), 1);
+    }
+
+    #[test]
+    fn find_counts_graphemes_both_ways() {
+        let row = Row::from("h\u{e9}llo w\u{f6}rld w\u{f6}rld");
+        let word = "w\u{f6}rld";
+        assert_eq!(row.find(word, 0, SearchDirection::Forward), Some(6));
+        assert_eq!(row.find(word, 7, SearchDirection::Forward), Some(12));
+        // Backwards the match has to end before `at`.
+        assert_eq!(row.find(word, 17, SearchDirection::Backward), Some(12));
+        assert_eq!(row.find(word, 16, SearchDirection::Backward), Some(6));
+        assert_eq!(row.find(word, 13, SearchDirection::Forward), None);
+        assert_eq!(row.find("", 0, SearchDirection::Forward), None);
+        assert_eq!(row.find(word, 18, SearchDirection::Forward), None);
+    }
+
+    #[test]
+    fn find_regex_returns_the_match_length() {
+        let row = Row::from("h\u{e9}llo w\u{f6}rld w\u{f6}rld");
+        let regex = Regex::new("w\\w+").unwrap();
+        assert_eq!(row.find_regex(&regex, 0, SearchDirection::Forward), Some((6, 5)));
+        assert_eq!(row.find_regex(&regex, 7, SearchDirection::Forward), Some((12, 5)));
+        assert_eq!(row.find_regex(&regex, 12, SearchDirection::Backward), Some((6, 5)));
+        assert_eq!(row.find_regex(&regex, 6, SearchDirection::Backward), None);
+    }
+
+    #[test]
+    fn every_match_is_highlighted() {
+        let mut row = Row::from("abab a ab");
+        row.highlight(Some("ab"));
+        assert_eq!(row.matches, [0..2, 2..4, 7..9]);
+        row.highlight_regex(Regex::new("a+").ok().as_ref());
+        assert_eq!(row.matches, [0..1, 2..3, 5..6, 7..8]);
+        row.highlight(None);
+        assert!(row.matches.is_empty());
+    }
+
+    // Highlights `rows` in order, one letter per grapheme: Comment, String,
+    // cHaracter, Keyword, Type, Number and `.` for plain text.
+    fn highlight(opts: &HighlightingOptions, rows: &[&str]) -> (Vec<String>, Open) {
+        let mut open = Open::None;
+        let mut kinds = Vec::new();
+        for text in rows {
+            let mut row = Row::from(*text);
+            open = row.highlight_syntax(opts, &open);
+            kinds.push(
+                row.highlighting
+                    .iter()
+                    .map(|kind| match kind {
+                        highlighting::Type::Comment => 'c',
+                        highlighting::Type::String => 's',
+                        highlighting::Type::Character => 'h',
+                        highlighting::Type::Keyword => 'k',
+                        highlighting::Type::Type => 't',
+                        highlighting::Type::Number => 'n',
+                        highlighting::Type::Heading => '#',
+                        highlighting::Type::None | highlighting::Type::Match => '.',
+                    })
+                    .collect(),
+            );
+        }
+        (kinds, open)
+    }
+
+    #[test]
+    fn block_comments_span_rows() {
+        let rust = FileType::from("main.rs");
+        let (kinds, open) = highlight(rust.highlighting_options(), &["a /* b", "c", "d */ 1"]);
+        assert_eq!(kinds, ["..cccc", "c", "cccc.n"]);
+        assert!(open == Open::None);
+        let (_, open) = highlight(rust.highlighting_options(), &["/* a", "b"]);
+        assert!(open == Open::Comment);
+    }
+
+    #[test]
+    fn python_triple_quoted_strings_span_rows() {
+        let python = FileType::from("main.py");
+        let (kinds, open) = highlight(python.highlighting_options(), &["x = \"\"\"a", "b \" c", "\"\"\" or"]);
+        assert_eq!(kinds, ["....ssss", "sssss", "sss.kk"]);
+        assert!(open == Open::None);
+    }
+
+    #[test]
+    fn rust_raw_strings_ignore_escapes() {
+        let rust = FileType::from("main.rs");
+        let (kinds, _) = highlight(rust.highlighting_options(), &["r#\"a\\\"b\"# fn"]);
+        assert_eq!(kinds, ["sssssssss.kk"]);
+        let (kinds, open) = highlight(rust.highlighting_options(), &["r#\"a", "\"b"]);
+        assert_eq!(kinds, ["ssss", "ss"]);
+        let close = String::from("\"#");
+        assert!(open == Open::String { close, escapes: false });
+    }
+
+    #[test]
+    fn character_literals() {
+        let rust = FileType::from("main.rs");
+        let (kinds, _) = highlight(rust.highlighting_options(), &["'a' '\\n' '\\u{1F600}' &'a"]);
+        assert_eq!(kinds, ["hhh.hhhh.hhhhhhhhhhh...."]);
+    }
+
+    #[test]
+    fn multi_byte_delimiters() {
+        let dir = std::env::temp_dir().join(format!("hecto-test-{}-delimiters", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("quotes.toml"),
+            "name = \"Quotes\"\nextensions = [\"q\"]\ncomment = \"\u{b6}\"\nmultiline_comment = [\"\u{ab}\", \"\u{bb}\"]\n",
+        )
+        .unwrap();
+        let (file_types, errors) = FileTypes::load_from(&dir);
+        let _ = fs::remove_dir_all(&dir);
+        assert!(errors.is_empty());
+        let quotes = file_types.detect("x.q");
+        let (kinds, open) = highlight(quotes.highlighting_options(), &["a \u{ab} \u{e9}", "b \u{bb} c \u{b6} d"]);
+        assert_eq!(kinds, ["..ccc", "ccc...ccc"]);
+        assert!(open == Open::None);
+    }
+
+    #[test]
+    fn selections_are_reversed() {
+        let row = Row::from("abcdef");
+        let (theme, _) = Theme::load(None, ColorMode::TrueColor);
+        let rendered = row.render(0, 80, Some(1..3), &theme, &Config::default());
+        assert_eq!(rendered, format!("a{}bc{}def", style::Invert, style::NoInvert));
+        // A selection running to the end of what's drawn is closed too.
+        let rendered = row.render(0, 4, Some(2..6), &theme, &Config::default());
+        assert_eq!(rendered, format!("ab{}cd{}", style::Invert, style::NoInvert));
+    }
+
+    #[test]
+    fn search_matches_without_colors_are_reversed() {
+        let mut row = Row::from("a bc d");
+        row.highlight(Some("bc"));
+        let (theme, _) = Theme::load(None, ColorMode::NoColor);
+        let rendered = row.render(0, 80, None, &theme, &Config::default());
+        assert_eq!(rendered, format!("a {}bc{} d", style::Invert, style::NoInvert));
+        let (theme, _) = Theme::load(None, ColorMode::TrueColor);
+        let rendered = row.render(0, 80, None, &theme, &Config::default());
+        assert!(!rendered.contains(&style::Invert.to_string()));
+    }
+
+    #[test]
+    fn truncating_keeps_whole_graphemes() {
+        assert_eq!(truncate_to_width("abc", 5), "abc");
+        assert_eq!(truncate_to_width("caf\u{e9} cr\u{e8}me", 6), "caf\u{e9} c");
+        assert_eq!(truncate_to_width("\u{65e5}\u{672c}\u{8a9e}", 5), "\u{65e5}\u{672c}");
+        assert_eq!(truncate_to_width("e\u{301}e\u{301}", 1), "e\u{301}");
+        assert_eq!(truncate_to_width("abc", 0), "");
+        assert_eq!(text_width("\u{65e5}\u{672c}\u{8a9e} \u{e9}"), 8);
+    }
+}
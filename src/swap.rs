@@ -0,0 +1,184 @@
+use std::cmp;
+use std::io;
+use std::process;
+
+// Unchanged lines shown around each change in a diff.
+const CONTEXT: usize = 2;
+// Above this many line pairs, the lines between the common start and end of
+// two texts are shown as replaced wholesale rather than diffed.
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+// Swap files start with this, followed by the ID of the process that wrote
+// them and a newline; the text comes after.
+const HEADER: &str = "hecto swap file, pid ";
+
+// What a swap file holding `text` contains when written by this process.
+pub fn contents(text: &str) -> String {
+    format!("{HEADER}{}\n{text}", process::id())
+}
+
+// The process ID and the text of a swap file's contents. Contents without a
+// header are all text.
+pub fn parse(contents: &str) -> (Option<u32>, &str) {
+    contents
+        .strip_prefix(HEADER)
+        .and_then(|rest| rest.split_once('\n'))
+        .and_then(|(pid, text)| Some((Some(pid.parse().ok()?), text)))
+        .unwrap_or((None, contents))
+}
+
+// Whether a process with this ID is running. Signal 0 checks without
+// sending anything; a process we may not signal still exists.
+pub fn is_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    if pid <= 0 {
+        return false;
+    }
+    // SAFETY: `kill` with signal 0 has no effect besides the error check.
+    let result = unsafe { libc::kill(pid, 0) };
+    result == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+// The lines that differ between `old` and `new`, prefixed with `-` or `+`,
+// with a few unchanged lines around them and a `@@` header per group.
+pub fn diff(old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let lines = diff_lines(&old, &new);
+    let mut result = Vec::new();
+    let mut old_line: usize = 1;
+    let mut new_line: usize = 1;
+    let mut last_shown = None;
+    for (index, (marker, line)) in lines.iter().enumerate() {
+        let near_change = lines
+            .iter()
+            .take(index.saturating_add(CONTEXT).saturating_add(1))
+            .skip(index.saturating_sub(CONTEXT))
+            .any(|(marker, _)| *marker != ' ');
+        if near_change {
+            if last_shown.is_none_or(|last: usize| last.saturating_add(1) != index) {
+                result.push(format!("@@ -{old_line} +{new_line} @@"));
+            }
+            result.push(format!("{marker} {line}"));
+            last_shown = Some(index);
+        }
+        if *marker != '+' {
+            old_line = old_line.saturating_add(1);
+        }
+        if *marker != '-' {
+            new_line = new_line.saturating_add(1);
+        }
+    }
+    if result.is_empty() {
+        return String::from("No differences");
+    }
+    result.join("\n")
+}
+
+// Every line of both texts, marked ` ` when both have it, `-` when only
+// `old` does and `+` when only `new` does.
+#[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(char, &'a str)> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+    let mut lines: Vec<(char, &str)> = old[..prefix].iter().map(|line| (' ', *line)).collect();
+    let (n, m) = (old_middle.len(), new_middle.len());
+    if n.saturating_mul(m) > MAX_DIFF_CELLS {
+        lines.extend(old_middle.iter().map(|line| ('-', *line)));
+        lines.extend(new_middle.iter().map(|line| ('+', *line)));
+    } else {
+        // common[i][j]: the longest common subsequence of the lines from
+        // old_middle[i] and new_middle[j] on.
+        let mut common = vec![vec![0_usize; m + 1]; n + 1];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                common[i][j] = if old_middle[i] == new_middle[j] {
+                    common[i + 1][j + 1] + 1
+                } else {
+                    cmp::max(common[i + 1][j], common[i][j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < n || j < m {
+            if i < n && j < m && old_middle[i] == new_middle[j] {
+                lines.push((' ', old_middle[i]));
+                i += 1;
+                j += 1;
+            } else if j == m || i < n && common[i + 1][j] >= common[i][j + 1] {
+                lines.push(('-', old_middle[i]));
+                i += 1;
+            } else {
+                lines.push(('+', new_middle[j]));
+                j += 1;
+            }
+        }
+    }
+    lines.extend(old[old.len() - suffix..].iter().map(|line| (' ', *line)));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contents_parse_back() {
+        let contents = contents("one\ntwo\n");
+        assert_eq!(parse(&contents), (Some(process::id()), "one\ntwo\n"));
+        assert_eq!(parse("no header\n"), (None, "no header\n"));
+        assert_eq!(parse("hecto swap file, pid x\ntext"), (None, "hecto swap file, pid x\ntext"));
+    }
+
+    #[test]
+    fn this_process_is_running() {
+        assert!(is_running(process::id()));
+        // init belongs to root, which doesn't stop it from counting.
+        assert!(is_running(1));
+    }
+
+    #[test]
+    fn finished_processes_are_not_running() {
+        let mut child = process::Command::new("true").spawn().unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+        assert!(!is_running(pid));
+        assert!(!is_running(0));
+        assert!(!is_running(u32::MAX));
+    }
+
+    #[test]
+    fn identical_texts_have_no_differences() {
+        assert_eq!(diff("a\nb\n", "a\nb\n"), "No differences");
+    }
+
+    #[test]
+    fn changed_line_shows_context() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n";
+        let new = "1\n2\n3\nfour\n5\n6\n7\n";
+        assert_eq!(diff(old, new), "@@ -2 +2 @@\n  2\n  3\n- 4\n+ four\n  5\n  6");
+    }
+
+    #[test]
+    fn distant_changes_get_their_own_hunks() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\n";
+        let new = "A\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+        assert_eq!(diff(old, new), "@@ -1 +1 @@\n- a\n+ A\n  b\n  c\n@@ -8 +8 @@\n  h\n  i\n+ j");
+    }
+
+    #[test]
+    fn insertions_and_deletions_are_aligned() {
+        let old = "keep\ndrop\nkeep too\n";
+        let new = "new\nkeep\nkeep too\nend\n";
+        assert_eq!(diff(old, new), "@@ -1 +1 @@\n+ new\n  keep\n- drop\n  keep too\n+ end");
+    }
+}
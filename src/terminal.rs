@@ -1,10 +1,37 @@
+use crate::clipboard;
+use crate::theme::ColorMode;
 use crate::Position;
-use std::io::{self, stdout, Write};
-use termion::event::Key;
+use std::env;
+use std::io::{self, stdout, Error, ErrorKind, Read, Write};
+use std::iter;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
 use termion::color;
-use termion::input::TermRead;
+use termion::event::{self as term_event, Key};
 use termion::raw::{IntoRawMode, RawTerminal};
 
+// How long to wait after an ESC byte before deciding it was the Esc key and
+// not the start of an escape sequence.
+const ESCAPE_TIMEOUT: Duration = Duration::from_millis(50);
+const BRACKETED_PASTE_ON: &str = "\x1b[?2004h";
+const BRACKETED_PASTE_OFF: &str = "\x1b[?2004l";
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+const FOCUS_REPORTING_ON: &str = "\x1b[?1004h";
+const FOCUS_REPORTING_OFF: &str = "\x1b[?1004l";
+const FOCUS_IN: &[u8] = b"\x1b[I";
+const FOCUS_OUT: &[u8] = b"\x1b[O";
+
+pub enum Event {
+    Key(Key),
+    // Everything between the bracketed paste markers, delivered in one go.
+    Paste(String),
+    // Only sent after report_focus.
+    FocusGained,
+    FocusLost,
+}
+
 pub struct Size {
     pub width: u16,
     pub height: u16,
@@ -13,11 +40,30 @@ pub struct Size {
 pub struct Terminal {
     size: Size,
     _stdout: RawTerminal<io::Stdout>,
+    input: Input,
 }
 
+// Bytes from stdin, read on a separate thread so that we can wait for input
+// with a timeout and never drop bytes between reads.
+struct Input(Receiver<u8>);
+
 impl Terminal {
     pub fn default() -> Result<Self, io::Error> {
         let size = termion::terminal_size()?;
+        let (sender, input) = mpsc::channel();
+        thread::spawn(move || {
+            for byte in io::stdin().bytes() {
+                match byte {
+                    Ok(byte) => {
+                        if sender.send(byte).is_err() {
+                            break;
+                        }
+                    }
+                    Err(_) => break,
+                }
+            }
+        });
+        print!("{BRACKETED_PASTE_ON}");
         Ok(Self {
             size: Size {
                 width: size.0,
@@ -27,8 +73,14 @@ impl Terminal {
             // Removes the need to keep a handle of stdout in main.
             // As long as terminal lives, we are in raw mode.
             _stdout: stdout().into_raw_mode()?,
+            input: Input(input),
         })
     }
+    // Asks the terminal to tell us when it gains or loses focus.
+    pub fn report_focus() {
+        print!("{FOCUS_REPORTING_ON}");
+    }
+
     pub fn size(&self) -> &Size {
         &self.size
     }
@@ -46,17 +98,39 @@ impl Terminal {
         print!("{}", termion::cursor::Goto(x, y));
     }
 
+    // Works out what the terminal can display from the environment. `NO_COLOR`
+    // wins over everything else (see https://no-color.org).
+    pub fn color_mode() -> ColorMode {
+        color_mode(
+            env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()),
+            &env::var("COLORTERM").unwrap_or_default(),
+            &env::var("TERM").unwrap_or_default(),
+        )
+    }
+
     pub fn flush() -> Result<(), std::io::Error> {
         io::stdout().flush()
     }
-    pub fn read_key() -> Result<Key, std::io::Error> {
+    pub fn read_key(&mut self) -> Result<Key, std::io::Error> {
         loop {
-            if let Some(key) = io::stdin().lock().keys().next() {
-                return key;
+            if let Event::Key(key) = self.read_event()? {
+                return Ok(key);
             }
         }
     }
 
+    // Blocks until the next key press or paste. Input we don't understand,
+    // such as mouse events, is skipped.
+    pub fn read_event(&mut self) -> Result<Event, std::io::Error> {
+        self.input.read_event()
+    }
+
+    // Like read_event, but gives up after `timeout` without input. Input we
+    // don't understand also counts as none.
+    pub fn read_event_timeout(&mut self, timeout: Duration) -> Result<Option<Event>, std::io::Error> {
+        self.input.read_event_timeout(timeout)
+    }
+
     pub fn cursor_hide() {
         print!("{}", termion::cursor::Hide);
     }
@@ -65,23 +139,201 @@ impl Terminal {
         print!("{}", termion::cursor::Show);
     }
 
+    // OSC 52: asks the terminal to put `text` on the system clipboard.
+    pub fn set_clipboard(text: &str) {
+        print!("\x1b]52;c;{}\x07", clipboard::base64(text.as_bytes()));
+    }
+
     pub fn clear_current_line() {
         print!("{}", termion::clear::CurrentLine);
     }
 
-    pub fn set_bg_color(color: termion::color::Rgb){
-        print!("{}", termion::color::Bg(color));
+    pub fn clear_until_newline() {
+        print!("{}", termion::clear::UntilNewline);
     }
 
-    pub fn reset_bg_color(){
-        print!("{}", termion::color::Bg(termion::color::Reset));
+    // Prints color sequences prepared by the theme for this terminal.
+    pub fn set_colors(colors: &str){
+        print!("{colors}");
     }
 
-    pub fn set_fg_color(color: color::Rgb){
-        print!("{}", color::Fg(color));
+    pub fn reset_bg_color(){
+        print!("{}", termion::color::Bg(termion::color::Reset));
     }
 
     pub fn reset_fg_color(){
         print!("{}", color::Fg(color::Reset));
     }
-}
\ No newline at end of file
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        print!("{BRACKETED_PASTE_OFF}{FOCUS_REPORTING_OFF}");
+        let _ = Self::flush();
+    }
+}
+
+impl Input {
+    fn read_event(&mut self) -> Result<Event, std::io::Error> {
+        loop {
+            let byte = self.next_byte()?;
+            if let Some(event) = self.parse_event(byte)? {
+                return Ok(event);
+            }
+        }
+    }
+
+    fn read_event_timeout(&mut self, timeout: Duration) -> Result<Option<Event>, std::io::Error> {
+        match self.0.recv_timeout(timeout) {
+            Ok(byte) => self.parse_event(byte),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(input_closed()),
+        }
+    }
+
+    // Reads the rest of the event that starts with `byte`.
+    fn parse_event(&mut self, byte: u8) -> Result<Option<Event>, std::io::Error> {
+        let next = if byte == 0x1b {
+            match self.0.recv_timeout(ESCAPE_TIMEOUT) {
+                Ok(next) => Some(next),
+                Err(RecvTimeoutError::Timeout) => return Ok(Some(Event::Key(Key::Esc))),
+                Err(RecvTimeoutError::Disconnected) => return Err(input_closed()),
+            }
+        } else {
+            None
+        };
+
+        let mut raw = vec![byte];
+        let input = &self.0;
+        let mut bytes = next
+            .into_iter()
+            .chain(iter::from_fn(|| input.recv().ok()))
+            .inspect(|byte| raw.push(*byte))
+            .map(Ok);
+        let parsed = term_event::parse_event(byte, &mut bytes);
+        drop(bytes);
+
+        if raw == PASTE_START {
+            return self.read_paste().map(Some);
+        }
+        if raw == FOCUS_IN {
+            return Ok(Some(Event::FocusGained));
+        }
+        if raw == FOCUS_OUT {
+            return Ok(Some(Event::FocusLost));
+        }
+        if let Ok(term_event::Event::Key(key)) = parsed {
+            return Ok(Some(Event::Key(key)));
+        }
+        Ok(None)
+    }
+
+    // Reads the pasted text up to the closing marker, without interpreting
+    // any of it as key presses.
+    fn read_paste(&mut self) -> Result<Event, std::io::Error> {
+        let mut pasted = Vec::new();
+        while !pasted.ends_with(PASTE_END) {
+            pasted.push(self.next_byte()?);
+        }
+        pasted.truncate(pasted.len().saturating_sub(PASTE_END.len()));
+        Ok(Event::Paste(String::from_utf8_lossy(&pasted).into_owned()))
+    }
+
+    fn next_byte(&self) -> Result<u8, std::io::Error> {
+        self.0.recv().map_err(|_| input_closed())
+    }
+}
+
+fn input_closed() -> Error {
+    Error::new(ErrorKind::UnexpectedEof, "input closed")
+}
+
+// The color mode for the given `NO_COLOR`, `COLORTERM` and `TERM` settings.
+fn color_mode(no_color: bool, colorterm: &str, term: &str) -> ColorMode {
+    if no_color {
+        return ColorMode::NoColor;
+    }
+    if colorterm == "truecolor" || colorterm == "24bit" {
+        return ColorMode::TrueColor;
+    }
+    if term == "dumb" {
+        ColorMode::NoColor
+    } else if term.contains("truecolor") || term.contains("direct") {
+        ColorMode::TrueColor
+    } else if term.contains("256color") {
+        ColorMode::Ansi256
+    } else {
+        ColorMode::Ansi16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::Sender;
+
+    // Input that has already received `bytes`. The sender keeps it open.
+    fn input(bytes: &[u8]) -> (Input, Sender<u8>) {
+        let (sender, receiver) = mpsc::channel();
+        for byte in bytes {
+            sender.send(*byte).unwrap();
+        }
+        (Input(receiver), sender)
+    }
+
+    fn read(input: &mut Input) -> Event {
+        input.read_event_timeout(Duration::from_secs(1)).unwrap().unwrap()
+    }
+
+    #[test]
+    fn pastes_arrive_whole() {
+        let (mut input, _sender) = input(b"\x1b[200~fn main() {\r\x1b[A\t}\x1b[201~x");
+        assert!(matches!(read(&mut input), Event::Paste(text) if text == "fn main() {\r\x1b[A\t}"));
+        assert!(matches!(read(&mut input), Event::Key(Key::Char('x'))));
+    }
+
+    #[test]
+    fn paste_waits_for_its_end_marker() {
+        let (mut input, sender) = input(b"\x1b[200~caf");
+        let reader = thread::spawn(move || read(&mut input));
+        for byte in "\u{e9}\x1b[201~".bytes() {
+            sender.send(byte).unwrap();
+        }
+        assert!(matches!(reader.join().unwrap(), Event::Paste(text) if text == "caf\u{e9}"));
+    }
+
+    #[test]
+    fn keys_and_focus_changes() {
+        let (mut input, _sender) = input("\x1b[A\u{e9}\x1b[I\x1b[O\x1b".as_bytes());
+        assert!(matches!(read(&mut input), Event::Key(Key::Up)));
+        assert!(matches!(read(&mut input), Event::Key(Key::Char('\u{e9}'))));
+        assert!(matches!(read(&mut input), Event::FocusGained));
+        assert!(matches!(read(&mut input), Event::FocusLost));
+        // Nothing follows the escape, so it's the Esc key.
+        assert!(matches!(read(&mut input), Event::Key(Key::Esc)));
+    }
+
+    #[test]
+    fn closed_input_is_an_error() {
+        let (mut input, sender) = input(b"\x1b[200~unfinished");
+        drop(sender);
+        assert!(input.read_event().is_err());
+    }
+
+    #[test]
+    fn color_mode_follows_the_environment() {
+        for (no_color, colorterm, term, mode) in [
+            (true, "truecolor", "xterm-256color", ColorMode::NoColor),
+            (false, "truecolor", "xterm", ColorMode::TrueColor),
+            (false, "24bit", "dumb", ColorMode::TrueColor),
+            (false, "", "dumb", ColorMode::NoColor),
+            (false, "", "xterm-direct", ColorMode::TrueColor),
+            (false, "", "xterm-256color", ColorMode::Ansi256),
+            (false, "yes", "screen-256color", ColorMode::Ansi256),
+            (false, "", "xterm", ColorMode::Ansi16),
+            (false, "", "", ColorMode::Ansi16),
+        ] {
+            assert!(color_mode(no_color, colorterm, term) == mode, "{colorterm:?} {term:?}");
+        }
+    }
+}
@@ -0,0 +1,364 @@
+use crate::config::{config_dir, describe_toml_error};
+use crate::highlighting;
+use serde::Deserialize;
+use std::fs;
+use termion::color::Rgb;
+
+// How many colors the terminal can show.
+#[derive(PartialEq, Clone, Copy)]
+pub enum ColorMode {
+    TrueColor,
+    Ansi256,
+    Ansi16,
+    // `NO_COLOR` is set or the terminal can't do colors at all.
+    NoColor,
+}
+
+// The colors of everything hecto draws. Colors are always given as RGB and
+// brought down to what the terminal supports when printed.
+pub struct Theme {
+    color_mode: ColorMode,
+    status_fg: Rgb,
+    status_bg: Rgb,
+    message_fg: Option<Rgb>,
+    // Without a selection color the selection is drawn in reverse video.
+    selection_bg: Option<Rgb>,
+    line_number_fg: Rgb,
+    number: Rgb,
+    search_match: Rgb,
+    string: Rgb,
+    character: Rgb,
+    comment: Rgb,
+    keyword: Rgb,
+    type_name: Rgb,
+    heading: Rgb,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            color_mode: ColorMode::TrueColor,
+            status_fg: Rgb(63, 63, 63),
+            status_bg: Rgb(239, 239, 239),
+            message_fg: None,
+            selection_bg: None,
+            line_number_fg: Rgb(128, 128, 128),
+            number: Rgb(220, 163, 163),
+            search_match: Rgb(38, 139, 210),
+            string: Rgb(211, 54, 130),
+            character: Rgb(108, 113, 196),
+            comment: Rgb(133, 153, 0),
+            keyword: Rgb(181, 137, 0),
+            type_name: Rgb(42, 161, 152),
+            heading: Rgb(203, 75, 22),
+        }
+    }
+}
+
+// The on-disk form of a theme. Anything left out keeps its default color.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct ThemeFile {
+    status_fg: Option<String>,
+    status_bg: Option<String>,
+    message_fg: Option<String>,
+    selection_bg: Option<String>,
+    line_number_fg: Option<String>,
+    #[serde(default)]
+    highlight: HighlightColors,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct HighlightColors {
+    number: Option<String>,
+    search_match: Option<String>,
+    string: Option<String>,
+    character: Option<String>,
+    comment: Option<String>,
+    keyword: Option<String>,
+    #[serde(rename = "type")]
+    type_name: Option<String>,
+    heading: Option<String>,
+}
+
+impl Theme {
+    // Loads `<config dir>/themes/<name>.toml` on top of the default theme.
+    // On failure the default theme is returned together with the reason.
+    pub fn load(name: Option<&str>, color_mode: ColorMode) -> (Self, Option<String>) {
+        let mut theme = Self {
+            color_mode,
+            ..Self::default()
+        };
+        let Some(name) = name else {
+            return (theme, None);
+        };
+        let Some(path) = config_dir().map(|dir| dir.join("themes").join(format!("{name}.toml"))) else {
+            return (theme, Some(format!("theme {name}: no config directory")));
+        };
+        let file = fs::read_to_string(&path)
+            .map_err(|error| error.to_string())
+            .and_then(|contents| {
+                toml::from_str::<ThemeFile>(&contents).map_err(|error| describe_toml_error(&error, &contents))
+            });
+        let error = match file {
+            Ok(file) => theme.apply(file).err(),
+            Err(error) => Some(error),
+        };
+        (theme, error.map(|error| format!("theme {name}: {error}")))
+    }
+
+    fn apply(&mut self, file: ThemeFile) -> Result<(), String> {
+        let set = |target: &mut Rgb, value: Option<String>| -> Result<(), String> {
+            if let Some(value) = value {
+                *target = parse_color(&value)?;
+            }
+            Ok(())
+        };
+        set(&mut self.status_fg, file.status_fg)?;
+        set(&mut self.status_bg, file.status_bg)?;
+        set(&mut self.line_number_fg, file.line_number_fg)?;
+        if let Some(value) = file.message_fg {
+            self.message_fg = Some(parse_color(&value)?);
+        }
+        if let Some(value) = file.selection_bg {
+            self.selection_bg = Some(parse_color(&value)?);
+        }
+        let highlight = file.highlight;
+        set(&mut self.number, highlight.number)?;
+        set(&mut self.search_match, highlight.search_match)?;
+        set(&mut self.string, highlight.string)?;
+        set(&mut self.character, highlight.character)?;
+        set(&mut self.comment, highlight.comment)?;
+        set(&mut self.keyword, highlight.keyword)?;
+        set(&mut self.type_name, highlight.type_name)?;
+        set(&mut self.heading, highlight.heading)?;
+        Ok(())
+    }
+
+    pub fn has_colors(&self) -> bool {
+        self.color_mode != ColorMode::NoColor
+    }
+
+    pub fn status_colors(&self) -> String {
+        format!("{}{}", self.fg(self.status_fg), self.bg(self.status_bg))
+    }
+
+    pub fn message_color(&self) -> String {
+        self.message_fg.map(|color| self.fg(color)).unwrap_or_default()
+    }
+
+    pub fn line_number_color(&self) -> String {
+        self.fg(self.line_number_fg)
+    }
+
+    // `None` means the selection should be drawn in reverse video.
+    pub fn selection_color(&self) -> Option<String> {
+        self.selection_bg
+            .filter(|_| self.has_colors())
+            .map(|color| self.bg(color))
+    }
+
+    // The sequence that switches to the color of a highlighting type.
+    pub fn highlight_color(&self, highlighting_type: highlighting::Type) -> String {
+        let color = match highlighting_type {
+            highlighting::Type::None => return self.reset_fg(),
+            highlighting::Type::Number => self.number,
+            highlighting::Type::Match => self.search_match,
+            highlighting::Type::String => self.string,
+            highlighting::Type::Character => self.character,
+            highlighting::Type::Comment => self.comment,
+            highlighting::Type::Keyword => self.keyword,
+            highlighting::Type::Type => self.type_name,
+            highlighting::Type::Heading => self.heading,
+        };
+        self.fg(color)
+    }
+
+    pub fn fg(&self, color: Rgb) -> String {
+        match self.color_mode {
+            ColorMode::TrueColor => format!("\x1b[38;2;{};{};{}m", color.0, color.1, color.2),
+            ColorMode::Ansi256 => format!("\x1b[38;5;{}m", to_ansi256(color)),
+            ColorMode::Ansi16 => {
+                let index = to_ansi16(color);
+                if index < 8 {
+                    format!("\x1b[{}m", 30_u8.saturating_add(index))
+                } else {
+                    format!("\x1b[{}m", 82_u8.saturating_add(index))
+                }
+            }
+            ColorMode::NoColor => String::new(),
+        }
+    }
+
+    pub fn bg(&self, color: Rgb) -> String {
+        match self.color_mode {
+            ColorMode::TrueColor => format!("\x1b[48;2;{};{};{}m", color.0, color.1, color.2),
+            ColorMode::Ansi256 => format!("\x1b[48;5;{}m", to_ansi256(color)),
+            ColorMode::Ansi16 => {
+                let index = to_ansi16(color);
+                if index < 8 {
+                    format!("\x1b[{}m", 40_u8.saturating_add(index))
+                } else {
+                    format!("\x1b[{}m", 92_u8.saturating_add(index))
+                }
+            }
+            ColorMode::NoColor => String::new(),
+        }
+    }
+
+    pub fn reset_fg(&self) -> String {
+        if self.has_colors() {
+            String::from("\x1b[39m")
+        } else {
+            String::new()
+        }
+    }
+
+    pub fn reset_bg(&self) -> String {
+        if self.has_colors() {
+            String::from("\x1b[49m")
+        } else {
+            String::new()
+        }
+    }
+}
+
+// Accepts `#rrggbb`.
+fn parse_color(value: &str) -> Result<Rgb, String> {
+    let invalid = || format!("invalid color {value:?}, expected #rrggbb");
+    let hex = value.strip_prefix('#').ok_or_else(invalid)?;
+    if hex.len() != 6 || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    let channel = |range| u8::from_str_radix(hex.get(range).unwrap_or_default(), 16).map_err(|_| invalid());
+    Ok(Rgb(channel(0..2)?, channel(2..4)?, channel(4..6)?))
+}
+
+// The closest color of the xterm 256 color palette: either the 6x6x6 color
+// cube or the grayscale ramp.
+#[allow(clippy::integer_arithmetic)]
+fn to_ansi256(color: Rgb) -> u8 {
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    let nearest_level = |value: u8| -> u8 {
+        let mut best = 0;
+        for (index, level) in LEVELS.iter().enumerate() {
+            if value.abs_diff(*level) < value.abs_diff(LEVELS[best]) {
+                best = index;
+            }
+        }
+        best as u8
+    };
+    let (r, g, b) = (nearest_level(color.0), nearest_level(color.1), nearest_level(color.2));
+    let cube = Rgb(LEVELS[r as usize], LEVELS[g as usize], LEVELS[b as usize]);
+
+    let average = (u16::from(color.0) + u16::from(color.1) + u16::from(color.2)) / 3;
+    let gray_index = (average.saturating_sub(3) / 10).min(23) as u8;
+    let gray_level = 8 + gray_index * 10;
+    let gray = Rgb(gray_level, gray_level, gray_level);
+
+    if distance(color, gray) < distance(color, cube) {
+        232 + gray_index
+    } else {
+        16 + 36 * r + 6 * g + b
+    }
+}
+
+// The closest of the 16 basic colors, using xterm's default palette.
+fn to_ansi16(color: Rgb) -> u8 {
+    const PALETTE: [Rgb; 16] = [
+        Rgb(0, 0, 0),
+        Rgb(205, 0, 0),
+        Rgb(0, 205, 0),
+        Rgb(205, 205, 0),
+        Rgb(0, 0, 238),
+        Rgb(205, 0, 205),
+        Rgb(0, 205, 205),
+        Rgb(229, 229, 229),
+        Rgb(127, 127, 127),
+        Rgb(255, 0, 0),
+        Rgb(0, 255, 0),
+        Rgb(255, 255, 0),
+        Rgb(92, 92, 255),
+        Rgb(255, 0, 255),
+        Rgb(0, 255, 255),
+        Rgb(255, 255, 255),
+    ];
+    let mut best = 0;
+    for (index, candidate) in PALETTE.iter().enumerate() {
+        if distance(color, *candidate) < distance(color, PALETTE[best]) {
+            best = index;
+        }
+    }
+    best as u8
+}
+
+fn distance(a: Rgb, b: Rgb) -> u32 {
+    let channel = |x: u8, y: u8| u32::from(x.abs_diff(y)).pow(2);
+    channel(a.0, b.0)
+        .saturating_add(channel(a.1, b.1))
+        .saturating_add(channel(a.2, b.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ansi256_uses_the_cube_for_colors_and_the_ramp_for_grays() {
+        for (color, index) in [
+            (Rgb(0, 0, 0), 16),
+            (Rgb(255, 0, 0), 196),
+            (Rgb(0, 255, 0), 46),
+            (Rgb(0, 0, 255), 21),
+            (Rgb(255, 255, 0), 226),
+            (Rgb(255, 255, 255), 231),
+            (Rgb(95, 135, 175), 67),
+            (Rgb(8, 8, 8), 232),
+            (Rgb(128, 128, 128), 244),
+            (Rgb(238, 238, 238), 255),
+        ] {
+            assert_eq!(to_ansi256(color), index, "{color:?}");
+        }
+    }
+
+    #[test]
+    fn ansi16_picks_the_nearest_basic_color() {
+        for (color, index) in [
+            (Rgb(0, 0, 0), 0),
+            (Rgb(200, 10, 10), 1),
+            (Rgb(255, 0, 0), 9),
+            (Rgb(0, 0, 240), 4),
+            (Rgb(120, 130, 125), 8),
+            (Rgb(230, 230, 230), 7),
+            (Rgb(255, 255, 255), 15),
+        ] {
+            assert_eq!(to_ansi16(color), index, "{color:?}");
+        }
+    }
+
+    #[test]
+    fn colors_are_hex_triplets() {
+        assert!(parse_color("#ff8000") == Ok(Rgb(255, 128, 0)));
+        assert!(parse_color("#00AaFf") == Ok(Rgb(0, 170, 255)));
+        for value in ["#zzzzzz", "ff8000", "#fff", "#ff80000", "#\u{e9}\u{e9}\u{e9}", ""] {
+            assert!(parse_color(value).is_err(), "{value:?}");
+        }
+    }
+
+    #[test]
+    fn escapes_match_the_color_mode() {
+        let theme = |color_mode| Theme {
+            color_mode,
+            ..Theme::default()
+        };
+        let red = Rgb(255, 0, 0);
+        assert_eq!(theme(ColorMode::TrueColor).fg(red), "\x1b[38;2;255;0;0m");
+        assert_eq!(theme(ColorMode::Ansi256).bg(red), "\x1b[48;5;196m");
+        assert_eq!(theme(ColorMode::Ansi16).fg(red), "\x1b[91m");
+        assert_eq!(theme(ColorMode::Ansi16).bg(Rgb(205, 0, 0)), "\x1b[41m");
+        let plain = theme(ColorMode::NoColor);
+        assert!(plain.fg(red).is_empty() && plain.reset_fg().is_empty());
+        assert!(plain.highlight_color(highlighting::Type::Match).is_empty());
+    }
+}
@@ -0,0 +1,569 @@
+use crate::Document;
+use crate::Position;
+use std::cmp;
+use termion::event::Key;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    Normal,
+    Insert,
+    Visual,
+}
+
+impl Mode {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Normal => "NORMAL",
+            Self::Insert => "INSERT",
+            Self::Visual => "VISUAL",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    WordForward,
+    WordBackward,
+    WordEnd,
+    LineStart,
+    LineEnd,
+    // `gg` and `G`; with a count both go to that line.
+    FirstLine,
+    LastLine,
+}
+
+impl Motion {
+    // Operators on these work on whole lines, e.g. `dj` deletes two lines.
+    fn is_linewise(self) -> bool {
+        matches!(self, Self::Up | Self::Down | Self::FirstLine | Self::LastLine)
+    }
+    // Operators on these include the character the motion lands on.
+    fn is_inclusive(self) -> bool {
+        self == Self::WordEnd
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum InsertAt {
+    Cursor,
+    After,
+    LineStart,
+    LineEnd,
+    LineBelow,
+    LineAbove,
+}
+
+// What the editor should do about a key pressed in modal mode.
+pub enum Action {
+    Move(Motion, Option<usize>),
+    Operate(Operator, Motion, Option<usize>),
+    // `dd`, `cc` and `yy`: the current line and `count - 1` below it.
+    OperateLines(Operator, usize),
+    OperateSelection(Operator),
+    Insert(InsertAt),
+    Paste { before: bool },
+    Undo,
+    Redo,
+    StartVisual,
+    ExitInsert,
+    // Esc in normal or visual mode.
+    Cancel,
+    // Part of a longer command, such as a count or the `d` of `dw`.
+    Pending,
+    // Not a modal key; let the regular key bindings handle it.
+    Pass,
+}
+
+// The state of the modal layer: the mode and a half typed command.
+pub struct Vim {
+    mode: Mode,
+    count: Option<usize>,
+    operator: Option<(Operator, Option<usize>)>,
+    // After a `g`, waiting for the second `g` of `gg`.
+    g_pending: bool,
+    // Whether the last yank or delete took whole lines, so `p` puts it on
+    // its own line.
+    pub linewise: bool,
+}
+
+impl Default for Vim {
+    fn default() -> Self {
+        Self {
+            mode: Mode::Normal,
+            count: None,
+            operator: None,
+            g_pending: false,
+            linewise: false,
+        }
+    }
+}
+
+impl Vim {
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn handle(&mut self, key: Key) -> Action {
+        if self.mode == Mode::Insert {
+            if key == Key::Esc {
+                self.mode = Mode::Normal;
+                return Action::ExitInsert;
+            }
+            return Action::Pass;
+        }
+        if let Key::Char(digit @ '0'..='9') = key {
+            // A leading 0 is the "start of line" motion, not a count.
+            if digit != '0' || self.count.is_some() {
+                let digit = digit.to_digit(10).unwrap_or(0) as usize;
+                self.count = Some(self.count.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+                return Action::Pending;
+            }
+        }
+        let count = self.count.take();
+        if self.g_pending {
+            self.g_pending = false;
+            if key == Key::Char('g') {
+                return self.motion(Motion::FirstLine, count);
+            }
+            self.operator = None;
+            return Action::Pending;
+        }
+        match key {
+            Key::Esc => {
+                self.operator = None;
+                self.mode = Mode::Normal;
+                Action::Cancel
+            }
+            Key::Ctrl('r') => self.simple(Action::Redo),
+            Key::Backspace | Key::Char('h') => self.motion(Motion::Left, count),
+            Key::Char('j' | '\n') => self.motion(Motion::Down, count),
+            Key::Char('k') => self.motion(Motion::Up, count),
+            Key::Char('l' | ' ') => self.motion(Motion::Right, count),
+            Key::Char('w') => self.motion(Motion::WordForward, count),
+            Key::Char('b') => self.motion(Motion::WordBackward, count),
+            Key::Char('e') => self.motion(Motion::WordEnd, count),
+            Key::Char('0') => self.motion(Motion::LineStart, count),
+            Key::Char('$') => self.motion(Motion::LineEnd, count),
+            Key::Char('G') => self.motion(Motion::LastLine, count),
+            Key::Char('g') => {
+                self.g_pending = true;
+                self.count = count;
+                Action::Pending
+            }
+            Key::Char(c @ ('d' | 'c' | 'y')) => {
+                let operator = match c {
+                    'd' => Operator::Delete,
+                    'c' => Operator::Change,
+                    _ => Operator::Yank,
+                };
+                if self.mode == Mode::Visual {
+                    return self.operate_selection(operator);
+                }
+                match self.operator.take() {
+                    Some((pending, pending_count)) if pending == operator => {
+                        if operator == Operator::Change {
+                            self.mode = Mode::Insert;
+                        }
+                        Action::OperateLines(operator, multiply(pending_count, count).unwrap_or(1))
+                    }
+                    _ => {
+                        self.operator = Some((operator, count));
+                        Action::Pending
+                    }
+                }
+            }
+            Key::Char('x') if self.mode == Mode::Visual => self.operate_selection(Operator::Delete),
+            Key::Char('x') => {
+                self.operator = Some((Operator::Delete, None));
+                self.motion(Motion::Right, count)
+            }
+            Key::Char(c @ ('i' | 'a' | 'I' | 'A' | 'o' | 'O')) if self.mode == Mode::Normal => {
+                self.operator = None;
+                self.mode = Mode::Insert;
+                Action::Insert(match c {
+                    'i' => InsertAt::Cursor,
+                    'a' => InsertAt::After,
+                    'I' => InsertAt::LineStart,
+                    'A' => InsertAt::LineEnd,
+                    'o' => InsertAt::LineBelow,
+                    _ => InsertAt::LineAbove,
+                })
+            }
+            Key::Char('p') => self.simple(Action::Paste { before: false }),
+            Key::Char('P') => self.simple(Action::Paste { before: true }),
+            Key::Char('u') => self.simple(Action::Undo),
+            Key::Char('v') if self.mode == Mode::Visual => {
+                self.mode = Mode::Normal;
+                Action::Cancel
+            }
+            Key::Char('v') => {
+                self.operator = None;
+                self.mode = Mode::Visual;
+                Action::StartVisual
+            }
+            // Anything else typed in normal mode is not text.
+            Key::Char(_) => self.simple(Action::Pending),
+            _ => self.simple(Action::Pass),
+        }
+    }
+
+    fn motion(&mut self, motion: Motion, count: Option<usize>) -> Action {
+        match self.operator.take() {
+            Some((operator, operator_count)) => {
+                if operator == Operator::Change {
+                    self.mode = Mode::Insert;
+                }
+                Action::Operate(operator, motion, multiply(operator_count, count))
+            }
+            None => Action::Move(motion, count),
+        }
+    }
+
+    fn operate_selection(&mut self, operator: Operator) -> Action {
+        self.mode = if operator == Operator::Change {
+            Mode::Insert
+        } else {
+            Mode::Normal
+        };
+        Action::OperateSelection(operator)
+    }
+
+    // An action that doesn't combine with a pending operator.
+    fn simple(&mut self, action: Action) -> Action {
+        self.operator = None;
+        action
+    }
+}
+
+// `2d3w` deletes six words.
+fn multiply(a: Option<usize>, b: Option<usize>) -> Option<usize> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.saturating_mul(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+// Where `motion` takes the cursor from `at`.
+pub fn target(document: &Document, at: Position, motion: Motion, count: Option<usize>) -> Position {
+    let times = count.unwrap_or(1);
+    let last_line = document.len().saturating_sub(1);
+    let line = |y: usize| cmp::min(y, last_line);
+    match motion {
+        Motion::Left => Position {
+            x: at.x.saturating_sub(times),
+            y: at.y,
+        },
+        Motion::Right => Position {
+            x: cmp::min(at.x.saturating_add(times), row_len(document, at.y)),
+            y: at.y,
+        },
+        Motion::Up => Position {
+            x: at.x,
+            y: at.y.saturating_sub(times),
+        },
+        Motion::Down => Position {
+            x: at.x,
+            y: line(at.y.saturating_add(times)),
+        },
+        Motion::LineStart => Position { x: 0, y: at.y },
+        Motion::LineEnd => Position {
+            x: row_len(document, at.y),
+            y: at.y,
+        },
+        Motion::FirstLine | Motion::LastLine => {
+            let y = match (motion, count) {
+                (_, Some(number)) => line(number.saturating_sub(1)),
+                (Motion::FirstLine, None) => 0,
+                _ => last_line,
+            };
+            Position {
+                x: first_non_blank(document, y),
+                y,
+            }
+        }
+        Motion::WordForward | Motion::WordBackward | Motion::WordEnd => {
+            let mut position = at;
+            for _ in 0..times {
+                position = match motion {
+                    Motion::WordForward => word_forward(document, position),
+                    Motion::WordBackward => word_backward(document, position),
+                    _ => word_end(document, position),
+                };
+            }
+            position
+        }
+    }
+}
+
+// The text an operator with `motion` works on, as a start and an end that
+// is not included. Linewise ranges are returned as first and last line.
+pub fn operator_range(
+    document: &Document,
+    at: Position,
+    operator: Operator,
+    motion: Motion,
+    count: Option<usize>,
+) -> (Position, Position, bool) {
+    // `cw` on a word changes to its end and keeps the following space.
+    let motion = if operator == Operator::Change && motion == Motion::WordForward && class_at(document, at) != 0 {
+        Motion::WordEnd
+    } else {
+        motion
+    };
+    let to = target(document, at, motion, count);
+    let (start, mut end) = if (to.y, to.x) < (at.y, at.x) { (to, at) } else { (at, to) };
+    if motion.is_linewise() {
+        return (start, end, true);
+    }
+    if motion.is_inclusive() {
+        end.x = cmp::min(end.x.saturating_add(1), row_len(document, end.y));
+    }
+    // When the last word moved over ends its line, `dw` stops at that line's
+    // end instead of joining it with the next one.
+    if motion == Motion::WordForward && end.y > start.y && end.x <= first_non_blank(document, end.y) {
+        let y = end.y.saturating_sub(1);
+        end = Position {
+            x: row_len(document, y),
+            y,
+        };
+    }
+    (start, end, false)
+}
+
+pub fn first_non_blank(document: &Document, y: usize) -> usize {
+    let len = row_len(document, y);
+    (0..len)
+        .find(|x| class_at(document, Position { x: *x, y }) != 0)
+        .unwrap_or(0)
+}
+
+// Whitespace (including the end of a line), word characters and punctuation.
+fn class_at(document: &Document, at: Position) -> u8 {
+    let c = document
+        .row(at.y)
+        .and_then(|row| row.substring(at.x, at.x.saturating_add(1)).chars().next());
+    match c {
+        None => 0,
+        Some(c) if c.is_whitespace() => 0,
+        Some(c) if c.is_alphanumeric() || c == '_' => 1,
+        Some(_) => 2,
+    }
+}
+
+fn row_len(document: &Document, y: usize) -> usize {
+    document.row(y).map_or(0, |row| row.len())
+}
+
+// The next position in the document, stepping over line ends.
+fn next(document: &Document, at: Position) -> Option<Position> {
+    if at.x < row_len(document, at.y) {
+        Some(Position {
+            x: at.x.saturating_add(1),
+            y: at.y,
+        })
+    } else if at.y.saturating_add(1) < document.len() {
+        Some(Position {
+            x: 0,
+            y: at.y.saturating_add(1),
+        })
+    } else {
+        None
+    }
+}
+
+fn previous(document: &Document, at: Position) -> Option<Position> {
+    if at.x > 0 {
+        Some(Position {
+            x: at.x.saturating_sub(1),
+            y: at.y,
+        })
+    } else if at.y > 0 {
+        let y = at.y.saturating_sub(1);
+        Some(Position {
+            x: row_len(document, y),
+            y,
+        })
+    } else {
+        None
+    }
+}
+
+fn is_empty_line(document: &Document, at: Position) -> bool {
+    at.x == 0 && row_len(document, at.y) == 0
+}
+
+// `w`: the start of the next word. Empty lines count as words.
+fn word_forward(document: &Document, at: Position) -> Position {
+    let mut class = class_at(document, at);
+    let mut position = at;
+    while let Some(next) = next(document, position) {
+        position = next;
+        let next_class = class_at(document, position);
+        if is_empty_line(document, position) || (next_class != 0 && next_class != class) {
+            break;
+        }
+        class = next_class;
+    }
+    position
+}
+
+// `b`: the start of this word, or of the previous one.
+fn word_backward(document: &Document, at: Position) -> Position {
+    let Some(mut position) = previous(document, at) else {
+        return at;
+    };
+    while class_at(document, position) == 0 && !is_empty_line(document, position) {
+        match previous(document, position) {
+            Some(previous) => position = previous,
+            None => return position,
+        }
+    }
+    let class = class_at(document, position);
+    while let Some(previous) = previous(document, position) {
+        if class == 0 || class_at(document, previous) != class {
+            break;
+        }
+        position = previous;
+    }
+    position
+}
+
+// `e`: the end of this word, or of the next one.
+fn word_end(document: &Document, at: Position) -> Position {
+    let Some(mut position) = next(document, at) else {
+        return at;
+    };
+    while class_at(document, position) == 0 {
+        match next(document, position) {
+            Some(next) => position = next,
+            None => return position,
+        }
+    }
+    let class = class_at(document, position);
+    while let Some(next) = next(document, position) {
+        if class_at(document, next) != class {
+            break;
+        }
+        position = next;
+    }
+    position
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+
+    fn document(text: &str) -> Document {
+        Document::from_text(text, &Config::default())
+    }
+
+    fn at(x: usize, y: usize) -> Position {
+        Position { x, y }
+    }
+
+    fn keys(vim: &mut Vim, keys: &str) -> Action {
+        let mut action = Action::Pass;
+        for c in keys.chars() {
+            action = vim.handle(Key::Char(c));
+        }
+        action
+    }
+
+    #[test]
+    fn counts_and_operators_combine() {
+        let mut vim = Vim::default();
+        assert!(matches!(
+            keys(&mut vim, "2d3w"),
+            Action::Operate(Operator::Delete, Motion::WordForward, Some(6))
+        ));
+        assert!(matches!(keys(&mut vim, "3dd"), Action::OperateLines(Operator::Delete, 3)));
+        assert!(matches!(keys(&mut vim, "10j"), Action::Move(Motion::Down, Some(10))));
+        // A leading zero is a motion, a later one part of the count.
+        assert!(matches!(keys(&mut vim, "0"), Action::Move(Motion::LineStart, None)));
+        assert!(matches!(keys(&mut vim, "5gg"), Action::Move(Motion::FirstLine, Some(5))));
+        assert!(matches!(keys(&mut vim, "x"), Action::Operate(Operator::Delete, Motion::Right, None)));
+    }
+
+    #[test]
+    fn modes_change_with_their_keys() {
+        let mut vim = Vim::default();
+        assert!(matches!(keys(&mut vim, "cw"), Action::Operate(Operator::Change, ..)));
+        assert!(vim.mode() == Mode::Insert);
+        assert!(matches!(keys(&mut vim, "x"), Action::Pass));
+        assert!(matches!(vim.handle(Key::Esc), Action::ExitInsert));
+        assert!(matches!(keys(&mut vim, "v"), Action::StartVisual));
+        assert!(matches!(keys(&mut vim, "y"), Action::OperateSelection(Operator::Yank)));
+        assert!(vim.mode() == Mode::Normal);
+        // Esc drops a half typed command.
+        keys(&mut vim, "d");
+        assert!(matches!(vim.handle(Key::Esc), Action::Cancel));
+        assert!(matches!(keys(&mut vim, "w"), Action::Move(Motion::WordForward, None)));
+        assert!(matches!(vim.handle(Key::Left), Action::Pass));
+    }
+
+    #[test]
+    fn word_motions() {
+        let document = document("foo.bar baz\n\n  qux");
+        assert!(target(&document, at(0, 0), Motion::WordForward, None) == at(3, 0));
+        assert!(target(&document, at(0, 0), Motion::WordForward, Some(3)) == at(8, 0));
+        // An empty line is a word of its own.
+        assert!(target(&document, at(8, 0), Motion::WordForward, None) == at(0, 1));
+        assert!(target(&document, at(0, 1), Motion::WordForward, None) == at(2, 2));
+        assert!(target(&document, at(2, 2), Motion::WordBackward, None) == at(0, 1));
+        assert!(target(&document, at(10, 0), Motion::WordBackward, None) == at(8, 0));
+        assert!(target(&document, at(0, 0), Motion::WordEnd, None) == at(2, 0));
+        assert!(target(&document, at(8, 0), Motion::WordEnd, None) == at(10, 0));
+        assert!(target(&document, at(10, 0), Motion::WordEnd, None) == at(4, 2));
+    }
+
+    #[test]
+    fn line_motions_stay_in_the_document() {
+        let document = document("one\n  two\nthree");
+        assert!(target(&document, at(1, 0), Motion::Down, Some(9)) == at(1, 2));
+        assert!(target(&document, at(1, 2), Motion::Up, Some(9)) == at(1, 0));
+        assert!(target(&document, at(0, 0), Motion::Right, Some(9)) == at(3, 0));
+        assert!(target(&document, at(2, 0), Motion::LineEnd, None) == at(3, 0));
+        assert!(target(&document, at(0, 0), Motion::FirstLine, Some(2)) == at(2, 1));
+        assert!(target(&document, at(0, 0), Motion::LastLine, None) == at(0, 2));
+    }
+
+    #[test]
+    fn operator_ranges() {
+        let document = document("foo bar\nbaz");
+        let range = operator_range(&document, at(0, 0), Operator::Delete, Motion::WordForward, None);
+        assert!(range == (at(0, 0), at(4, 0), false));
+        // `cw` keeps the space after the word.
+        let range = operator_range(&document, at(0, 0), Operator::Change, Motion::WordForward, None);
+        assert!(range == (at(0, 0), at(3, 0), false));
+        let range = operator_range(&document, at(4, 0), Operator::Delete, Motion::WordEnd, None);
+        assert!(range == (at(4, 0), at(7, 0), false));
+        let range = operator_range(&document, at(5, 0), Operator::Delete, Motion::WordBackward, None);
+        assert!(range == (at(4, 0), at(5, 0), false));
+        let range = operator_range(&document, at(1, 1), Operator::Yank, Motion::Up, None);
+        assert!(range == (at(1, 0), at(1, 1), true));
+    }
+
+    #[test]
+    fn dw_on_the_last_word_stays_on_its_line() {
+        let document = document("foo bar\n  baz\n\nqux");
+        let range = operator_range(&document, at(4, 0), Operator::Delete, Motion::WordForward, None);
+        assert!(range == (at(4, 0), at(7, 0), false));
+        let range = operator_range(&document, at(2, 1), Operator::Delete, Motion::WordForward, None);
+        assert!(range == (at(2, 1), at(5, 1), false));
+        // With a count the words on earlier lines still go.
+        let range = operator_range(&document, at(4, 0), Operator::Delete, Motion::WordForward, Some(2));
+        assert!(range == (at(4, 0), at(5, 1), false));
+    }
+}
@@ -0,0 +1,281 @@
+use crate::Position;
+
+// A part of the screen, in terminal cells.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Direction {
+    // One pane above the other.
+    Horizontal,
+    // Side by side, with a separator column between them.
+    Vertical,
+}
+
+// A pane's window into a buffer. Several views can show the same buffer,
+// each with its own cursor and scroll offset.
+#[derive(Clone, Copy, Default)]
+pub struct View {
+    pub buffer: usize,
+    pub cursor_position: Position,
+    pub offset: Position,
+}
+
+// How the screen is divided into panes. Each pane shows the view with the
+// index it holds.
+pub enum Layout {
+    Pane(usize),
+    Split {
+        direction: Direction,
+        // The share of the space that goes to `first`, in percent.
+        percent: usize,
+        first: Box<Layout>,
+        second: Box<Layout>,
+    },
+}
+
+// A pane needs room for at least one line of text and its status line.
+const MIN_HEIGHT: usize = 2;
+const MIN_WIDTH: usize = 1;
+
+impl Layout {
+    // Divides `pane` in two, with `new_pane` getting the second half.
+    pub fn split(&mut self, pane: usize, direction: Direction, new_pane: usize) -> bool {
+        match self {
+            Self::Pane(index) if *index == pane => {
+                *self = Self::Split {
+                    direction,
+                    percent: 50,
+                    first: Box::new(Self::Pane(pane)),
+                    second: Box::new(Self::Pane(new_pane)),
+                };
+                true
+            }
+            Self::Pane(_) => false,
+            Self::Split { first, second, .. } => {
+                first.split(pane, direction, new_pane) || second.split(pane, direction, new_pane)
+            }
+        }
+    }
+
+    // Takes `pane` out and gives its space to the pane or panes next to it.
+    // Panes after it are renumbered, as they are in the list of views.
+    pub fn remove(&mut self, pane: usize) -> bool {
+        let removed = self.remove_pane(pane);
+        if removed {
+            self.renumber(pane);
+        }
+        removed
+    }
+
+    fn remove_pane(&mut self, pane: usize) -> bool {
+        let Self::Split { first, second, .. } = self else {
+            return false;
+        };
+        let remaining = if matches!(**first, Self::Pane(index) if index == pane) {
+            second
+        } else if matches!(**second, Self::Pane(index) if index == pane) {
+            first
+        } else {
+            return first.remove_pane(pane) || second.remove_pane(pane);
+        };
+        let remaining = std::mem::replace(&mut **remaining, Self::Pane(0));
+        *self = remaining;
+        true
+    }
+
+    fn renumber(&mut self, removed: usize) {
+        match self {
+            Self::Pane(index) => {
+                if *index > removed {
+                    *index = index.saturating_sub(1);
+                }
+            }
+            Self::Split { first, second, .. } => {
+                first.renumber(removed);
+                second.renumber(removed);
+            }
+        }
+    }
+
+    // Moves the edge of the innermost split around `pane` by `percent`, so
+    // that `pane` grows for positive values.
+    pub fn resize(&mut self, pane: usize, percent: isize) -> bool {
+        let Self::Split {
+            percent: share,
+            first,
+            second,
+            ..
+        } = self
+        else {
+            return false;
+        };
+        if first.resize(pane, percent) || second.resize(pane, percent) {
+            return true;
+        }
+        let change = if first.contains(pane) {
+            percent
+        } else if second.contains(pane) {
+            percent.saturating_neg()
+        } else {
+            return false;
+        };
+        *share = share.saturating_add_signed(change).clamp(10, 90);
+        true
+    }
+
+    fn contains(&self, pane: usize) -> bool {
+        match self {
+            Self::Pane(index) => *index == pane,
+            Self::Split { first, second, .. } => first.contains(pane) || second.contains(pane),
+        }
+    }
+
+    // Every pane with the part of `area` it covers, from left to right and
+    // top to bottom.
+    pub fn panes(&self, area: Rect) -> Vec<(usize, Rect)> {
+        let mut panes = Vec::new();
+        self.collect_panes(area, &mut panes);
+        panes
+    }
+
+    #[allow(clippy::integer_arithmetic)]
+    fn collect_panes(&self, area: Rect, panes: &mut Vec<(usize, Rect)>) {
+        match self {
+            Self::Pane(index) => panes.push((*index, area)),
+            Self::Split {
+                direction: Direction::Horizontal,
+                percent,
+                first,
+                second,
+            } => {
+                let top = share(area.height, *percent, MIN_HEIGHT);
+                first.collect_panes(Rect { height: top, ..area }, panes);
+                second.collect_panes(
+                    Rect {
+                        y: area.y + top,
+                        height: area.height - top,
+                        ..area
+                    },
+                    panes,
+                );
+            }
+            Self::Split {
+                direction: Direction::Vertical,
+                percent,
+                first,
+                second,
+            } => {
+                // One column goes to the separator.
+                let available = area.width.saturating_sub(1);
+                let left = share(available, *percent, MIN_WIDTH);
+                first.collect_panes(Rect { width: left, ..area }, panes);
+                second.collect_panes(
+                    Rect {
+                        x: area.x + left + 1,
+                        width: available - left,
+                        ..area
+                    },
+                    panes,
+                );
+            }
+        }
+    }
+}
+
+// `percent` of `total`, leaving at least `min` on both sides when possible.
+#[allow(clippy::integer_arithmetic)]
+fn share(total: usize, percent: usize, min: usize) -> usize {
+    let size = total * percent / 100;
+    if total < min * 2 {
+        total / 2
+    } else {
+        size.clamp(min, total - min)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: usize, y: usize, width: usize, height: usize) -> Rect {
+        Rect { x, y, width, height }
+    }
+
+    const SCREEN: Rect = Rect {
+        x: 0,
+        y: 0,
+        width: 81,
+        height: 24,
+    };
+
+    #[test]
+    fn split_horizontally() {
+        let mut layout = Layout::Pane(0);
+        assert!(layout.split(0, Direction::Horizontal, 1));
+        assert!(layout.panes(SCREEN) == [(0, rect(0, 0, 81, 12)), (1, rect(0, 12, 81, 12))]);
+    }
+
+    #[test]
+    fn split_vertically_leaves_a_separator() {
+        let mut layout = Layout::Pane(0);
+        assert!(layout.split(0, Direction::Vertical, 1));
+        assert!(layout.panes(SCREEN) == [(0, rect(0, 0, 40, 24)), (1, rect(41, 0, 40, 24))]);
+        // Splits nest inside the pane that was split.
+        assert!(layout.split(1, Direction::Horizontal, 2));
+        assert!(!layout.split(5, Direction::Horizontal, 3));
+        assert!(
+            layout.panes(SCREEN)
+                == [
+                    (0, rect(0, 0, 40, 24)),
+                    (1, rect(41, 0, 40, 12)),
+                    (2, rect(41, 12, 40, 12))
+                ]
+        );
+    }
+
+    #[test]
+    fn removing_a_pane_gives_its_space_away() {
+        let mut layout = Layout::Pane(0);
+        layout.split(0, Direction::Vertical, 1);
+        layout.split(1, Direction::Horizontal, 2);
+        assert!(layout.remove(1));
+        // The pane that was 2 is now 1.
+        assert!(layout.panes(SCREEN) == [(0, rect(0, 0, 40, 24)), (1, rect(41, 0, 40, 24))]);
+        assert!(layout.remove(0));
+        assert!(layout.panes(SCREEN) == [(0, SCREEN)]);
+        // The last pane stays.
+        assert!(!layout.remove(0));
+        assert!(layout.panes(SCREEN) == [(0, SCREEN)]);
+    }
+
+    #[test]
+    fn resizing_stops_at_the_limits() {
+        let mut layout = Layout::Pane(0);
+        assert!(!layout.resize(0, 10));
+        layout.split(0, Direction::Horizontal, 1);
+        assert!(layout.resize(0, 25));
+        assert!(layout.panes(SCREEN) == [(0, rect(0, 0, 81, 18)), (1, rect(0, 18, 81, 6))]);
+        assert!(layout.resize(1, 1000));
+        assert!(layout.panes(SCREEN) == [(0, rect(0, 0, 81, 2)), (1, rect(0, 2, 81, 22))]);
+        assert!(layout.resize(1, -1000));
+        assert!(layout.panes(SCREEN) == [(0, rect(0, 0, 81, 21)), (1, rect(0, 21, 81, 3))]);
+        assert!(!layout.resize(2, 10));
+    }
+
+    #[test]
+    fn small_areas_keep_the_minimum_size() {
+        let mut layout = Layout::Pane(0);
+        layout.split(0, Direction::Horizontal, 1);
+        layout.resize(0, -40);
+        let area = rect(0, 0, 10, 5);
+        assert!(layout.panes(area) == [(0, rect(0, 0, 10, 2)), (1, rect(0, 2, 10, 3))]);
+        let area = rect(0, 0, 10, 3);
+        assert!(layout.panes(area) == [(0, rect(0, 0, 10, 1)), (1, rect(0, 1, 10, 2))]);
+    }
+}